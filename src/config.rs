@@ -0,0 +1,225 @@
+use std::fs;
+
+use crate::game_mode::ModeKind;
+use crate::garbage::HolePolicy;
+use crate::gravity::{GravityCurve, LevelLines};
+use crate::randomizer::RandomizerKind;
+use crate::scoring::ScoringKind;
+
+const CONFIG_FILE: &str = "config.txt";
+const MAX_PREVIEW: usize = 6;
+const MIN_BOARD_WIDTH: usize = 4; // 方块最宽为4格
+const MIN_BOARD_HEIGHT: usize = 4; // 竖着的I方块有4格高
+
+// 锁定延迟的重置方式
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum LockReset {
+    Move, // 平移或旋转成功就重新计时
+    Step, // 只有方块下降到新的一行才重新计时
+}
+
+// 游戏的设置，从配置文件中读取，没有配置的项使用默认值
+#[derive(Clone, Debug)]
+pub struct Config {
+    pub mode: ModeKind,
+    pub width: usize, // 网格的列数
+    pub height: usize, // 网格的行数
+    pub buffer_rows: usize, // 可见区域上方看不到的缓冲行数，方块在这里出现
+    pub partial_lock_out: bool, // 方块有一部分在可见区域上方合并时是否结束游戏
+    pub top_out: bool, // 网格堆满时是否结束游戏，由游戏模式设置
+    pub garbage_holes: HolePolicy, // 垃圾行空洞的位置
+    pub dig_rows: usize, // 挖掘模式开始时的垃圾行数
+    pub dig_infinite: bool, // 挖掘模式是否不断补充垃圾行
+    pub start_level: u32,
+    pub gravity: GravityCurve, // 每个级别的下落速度
+    pub level_lines: LevelLines, // 升级需要消除的行数
+    pub randomizer: RandomizerKind,
+    pub scoring: ScoringKind,
+    pub preview_len: usize, // 预览的方块个数，0到6个
+    pub ghost: bool, // 是否显示方块落地位置的影子
+    pub lock_delay: u32, // 方块落地后多少毫秒才锁定
+    pub lock_resets: u32, // 落地后最多重新计时的次数，避免无限拖延
+    pub lock_reset: LockReset,
+    pub das: u32, // 按住左右键多少毫秒后开始自动平移
+    pub arr: u32, // 自动平移每一格的间隔毫秒数，0表示直接移到底
+    pub soft_drop_factor: u32, // 按住下键时下落速度的倍数
+    pub save_slot: u32, // 退出时保存游戏的存档位，0表示不保存
+}
+
+impl Default for Config {
+    fn default() -> Config {
+        Config {
+            mode: ModeKind::Marathon,
+            width: 10,
+            height: 16,
+            buffer_rows: 20,
+            partial_lock_out: false,
+            top_out: true,
+            garbage_holes: HolePolicy::Messy(30),
+            dig_rows: 10,
+            dig_infinite: false,
+            start_level: 1,
+            gravity: GravityCurve::Guideline,
+            level_lines: LevelLines::Fixed(10),
+            randomizer: RandomizerKind::Bag7,
+            scoring: ScoringKind::Guideline,
+            preview_len: 5,
+            ghost: true,
+            lock_delay: 500,
+            lock_resets: 15,
+            lock_reset: LockReset::Move,
+            das: 167,
+            arr: 33,
+            soft_drop_factor: 20,
+            save_slot: 1,
+        }
+    }
+}
+
+impl Config {
+    // 配置文件每行为 key = value，#开头的行是注释
+    pub fn load() -> Config {
+        let mut config = Config::default();
+        if let Ok(content) = fs::read_to_string(CONFIG_FILE) {
+            for line in content.lines() {
+                let line = line.trim();
+                if line.is_empty() || line.starts_with('#') {
+                    continue
+                }
+                if let Some((key, value)) = line.split_once('=') {
+                    if !config.set(key.trim(), value.trim()) {
+                        println!("Ignoring invalid config line: {}", line);
+                    }
+                }
+            }
+        }
+        config
+    }
+
+    // 转换成配置文件的格式，保存游戏时和游戏状态一起保存
+    pub fn save(&self) -> Vec<String> {
+        vec![
+            format!("mode = {}", self.mode.name()),
+            format!("width = {}", self.width),
+            format!("height = {}", self.height),
+            format!("buffer_rows = {}", self.buffer_rows),
+            format!("partial_lock_out = {}", self.partial_lock_out),
+            format!("garbage_holes = {}", self.garbage_holes.to_config()),
+            format!("dig_rows = {}", self.dig_rows),
+            format!("dig_infinite = {}", self.dig_infinite),
+            format!("start_level = {}", self.start_level),
+            format!("gravity = {}", self.gravity.to_config()),
+            format!("level_lines = {}", self.level_lines.to_config()),
+            format!("randomizer = {}", self.randomizer.name()),
+            format!("scoring = {}", self.scoring.name()),
+            format!("preview = {}", self.preview_len),
+            format!("ghost = {}", self.ghost),
+            format!("lock_delay = {}", self.lock_delay),
+            format!("lock_resets = {}", self.lock_resets),
+            format!("lock_reset = {}", match self.lock_reset {
+                LockReset::Move => "move",
+                LockReset::Step => "step",
+            }),
+            format!("das = {}", self.das),
+            format!("arr = {}", self.arr),
+            format!("soft_drop_factor = {}", self.soft_drop_factor),
+            format!("save_slot = {}", self.save_slot),
+        ]
+    }
+
+    pub fn set(&mut self, key: &str, value: &str) -> bool {
+        match key {
+            "mode" => match ModeKind::from_name(value) {
+                Some(mode) => self.mode = mode,
+                None => return false,
+            },
+            "width" => match value.parse::<usize>() {
+                Ok(width) if width >= MIN_BOARD_WIDTH => self.width = width,
+                _ => return false,
+            },
+            "height" => match value.parse::<usize>() {
+                Ok(height) if height >= MIN_BOARD_HEIGHT => self.height = height,
+                _ => return false,
+            },
+            "buffer_rows" => match value.parse::<usize>() {
+                Ok(rows) => self.buffer_rows = rows,
+                Err(_) => return false,
+            },
+            "partial_lock_out" => match value.parse::<bool>() {
+                Ok(partial) => self.partial_lock_out = partial,
+                Err(_) => return false,
+            },
+            "garbage_holes" => match HolePolicy::from_config(value) {
+                Some(policy) => self.garbage_holes = policy,
+                None => return false,
+            },
+            "dig_rows" => match value.parse::<usize>() {
+                Ok(rows) if rows > 0 => self.dig_rows = rows,
+                _ => return false,
+            },
+            "dig_infinite" => match value.parse::<bool>() {
+                Ok(infinite) => self.dig_infinite = infinite,
+                Err(_) => return false,
+            },
+            "start_level" => match value.parse::<u32>() {
+                Ok(level) if level > 0 => self.start_level = level,
+                _ => return false,
+            },
+            "gravity" => match GravityCurve::from_config(value) {
+                Some(gravity) => self.gravity = gravity,
+                None => return false,
+            },
+            "level_lines" => match LevelLines::from_config(value) {
+                Some(level_lines) => self.level_lines = level_lines,
+                None => return false,
+            },
+            "randomizer" => match RandomizerKind::from_name(value) {
+                Some(randomizer) => self.randomizer = randomizer,
+                None => return false,
+            },
+            "scoring" => match ScoringKind::from_name(value) {
+                Some(scoring) => self.scoring = scoring,
+                None => return false,
+            },
+            "preview" => match value.parse::<usize>() {
+                Ok(len) => self.preview_len = len.min(MAX_PREVIEW),
+                Err(_) => return false,
+            },
+            "ghost" => match value.parse::<bool>() {
+                Ok(ghost) => self.ghost = ghost,
+                Err(_) => return false,
+            },
+            "lock_delay" => match value.parse::<u32>() {
+                Ok(delay) => self.lock_delay = delay,
+                Err(_) => return false,
+            },
+            "lock_resets" => match value.parse::<u32>() {
+                Ok(resets) => self.lock_resets = resets,
+                Err(_) => return false,
+            },
+            "lock_reset" => match value {
+                "move" => self.lock_reset = LockReset::Move,
+                "step" => self.lock_reset = LockReset::Step,
+                _ => return false,
+            },
+            "das" => match value.parse::<u32>() {
+                Ok(das) => self.das = das,
+                Err(_) => return false,
+            },
+            "arr" => match value.parse::<u32>() {
+                Ok(arr) => self.arr = arr,
+                Err(_) => return false,
+            },
+            "soft_drop_factor" => match value.parse::<u32>() {
+                Ok(factor) if factor > 0 => self.soft_drop_factor = factor,
+                _ => return false,
+            },
+            "save_slot" => match value.parse::<u32>() {
+                Ok(slot) => self.save_slot = slot,
+                Err(_) => return false,
+            },
+            _ => return false,
+        }
+        true
+    }
+}
@@ -0,0 +1,340 @@
+use crate::config::Config;
+use crate::garbage::GARBAGE;
+use crate::gravity::GravityCurve;
+use crate::tetris::{Tetris, TICK_MICROS};
+
+const MARATHON_LINES: u32 = 150;
+const MARATHON_LEVELS: u32 = 15;
+const SPRINT_LINES: u32 = 40;
+const ULTRA_MICROS: u64 = 2 * 60 * 1_000_000;
+// 生存模式1级时每5秒升起一行垃圾，每升一级快0.4秒，最快1秒一行
+const SURVIVAL_MICROS: u64 = 5_000_000;
+const SURVIVAL_LEVEL_MICROS: u64 = 400_000;
+const SURVIVAL_MIN_MICROS: u64 = 1_000_000;
+
+// 游戏模式，在Tetris上定义目标、计时和结束条件
+pub trait GameMode {
+    fn name(&self) -> &'static str;
+    // 开始前调整配置，例如禅模式没有重力
+    fn configure(&self, _config: &mut Config) {}
+    // 游戏创建好之后调用，例如挖掘模式在网格底部放上垃圾行
+    fn start(&mut self, _tetris: &mut Tetris) {}
+    // 每个tick之后调用，返回true表示达到了目标，游戏结束
+    fn tick(&mut self, tetris: &mut Tetris) -> bool;
+    // 游戏中显示的目标或者计时
+    fn status(&self, tetris: &Tetris) -> String;
+    // 结果画面在模式名称下面显示的内容，每个元素一行
+    fn results(&self, tetris: &Tetris) -> Vec<String>;
+    // 保存游戏时把模式自己的状态转换成一行文字，没有状态的模式不需要实现
+    fn save(&self) -> String {
+        String::new()
+    }
+    fn restore(&mut self, _state: &str) -> bool {
+        true
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum ModeKind {
+    Marathon,
+    Sprint,
+    Ultra,
+    Zen,
+    Dig,
+    Survival,
+}
+
+impl ModeKind {
+    pub fn from_name(name: &str) -> Option<ModeKind> {
+        match name {
+            "marathon" => Some(ModeKind::Marathon),
+            "sprint" => Some(ModeKind::Sprint),
+            "ultra" => Some(ModeKind::Ultra),
+            "zen" => Some(ModeKind::Zen),
+            "dig" => Some(ModeKind::Dig),
+            "survival" => Some(ModeKind::Survival),
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            ModeKind::Marathon => "marathon",
+            ModeKind::Sprint => "sprint",
+            ModeKind::Ultra => "ultra",
+            ModeKind::Zen => "zen",
+            ModeKind::Dig => "dig",
+            ModeKind::Survival => "survival",
+        }
+    }
+
+    pub fn create(self) -> Box<dyn GameMode> {
+        match self {
+            ModeKind::Marathon => Box::new(Marathon),
+            ModeKind::Sprint => Box::new(Sprint),
+            ModeKind::Ultra => Box::new(Ultra),
+            ModeKind::Zen => Box::new(Zen),
+            ModeKind::Dig => Box::new(Dig::new()),
+            ModeKind::Survival => Box::new(Survival::new()),
+        }
+    }
+}
+
+// 把tick数显示为 分:秒.毫秒
+pub fn format_time(ticks: u64) -> String {
+    let millis = ticks * TICK_MICROS / 1000;
+    format!("{}:{:02}.{:03}", millis / 60_000, millis / 1000 % 60, millis % 1000)
+}
+
+fn micros(tetris: &Tetris) -> u64 {
+    tetris.ticks() * TICK_MICROS
+}
+
+// 马拉松，消除150行或者通过15个级别
+pub struct Marathon;
+
+impl GameMode for Marathon {
+    fn name(&self) -> &'static str {
+        "Marathon"
+    }
+
+    fn tick(&mut self, tetris: &mut Tetris) -> bool {
+        tetris.nb_lines >= MARATHON_LINES ||
+            tetris.current_level >= tetris.config().start_level + MARATHON_LEVELS
+    }
+
+    fn status(&self, tetris: &Tetris) -> String {
+        format!("Lines: {}/{}", tetris.nb_lines, MARATHON_LINES)
+    }
+
+    fn results(&self, tetris: &Tetris) -> Vec<String> {
+        vec![
+            if tetris.completed { "Complete!" } else { "Game over" }.to_string(),
+            format!("Score: {}", tetris.score),
+            format!("Lines: {}", tetris.nb_lines),
+            format!("Level: {}", tetris.current_level),
+            format!("Time: {}", format_time(tetris.ticks())),
+        ]
+    }
+}
+
+// 竞速，尽快消除40行
+pub struct Sprint;
+
+impl GameMode for Sprint {
+    fn name(&self) -> &'static str {
+        "Sprint 40L"
+    }
+
+    fn tick(&mut self, tetris: &mut Tetris) -> bool {
+        tetris.nb_lines >= SPRINT_LINES
+    }
+
+    fn status(&self, tetris: &Tetris) -> String {
+        format!("{} {}/{}", format_time(tetris.ticks()), tetris.nb_lines.min(SPRINT_LINES), SPRINT_LINES)
+    }
+
+    fn results(&self, tetris: &Tetris) -> Vec<String> {
+        if tetris.completed {
+            vec![
+                "Complete!".to_string(),
+                format!("Time: {}", format_time(tetris.ticks())),
+            ]
+        } else {
+            vec![
+                "Game over".to_string(),
+                format!("Lines: {}/{}", tetris.nb_lines, SPRINT_LINES),
+            ]
+        }
+    }
+}
+
+// 限时两分钟，比较分数
+pub struct Ultra;
+
+impl GameMode for Ultra {
+    fn name(&self) -> &'static str {
+        "Ultra"
+    }
+
+    fn tick(&mut self, tetris: &mut Tetris) -> bool {
+        micros(tetris) >= ULTRA_MICROS
+    }
+
+    fn status(&self, tetris: &Tetris) -> String {
+        let left = ULTRA_MICROS.saturating_sub(micros(tetris)) / TICK_MICROS;
+        format!("Left {}", format_time(left))
+    }
+
+    fn results(&self, tetris: &Tetris) -> Vec<String> {
+        vec![
+            if tetris.completed { "Time up!" } else { "Game over" }.to_string(),
+            format!("Score: {}", tetris.score),
+            format!("Lines: {}", tetris.nb_lines),
+        ]
+    }
+}
+
+// 禅模式，没有重力，网格堆满时清空继续，不会结束
+pub struct Zen;
+
+impl GameMode for Zen {
+    fn name(&self) -> &'static str {
+        "Zen"
+    }
+
+    fn configure(&self, config: &mut Config) {
+        config.gravity = GravityCurve::Table(vec![0.0]);
+        config.top_out = false;
+    }
+
+    fn tick(&mut self, _tetris: &mut Tetris) -> bool {
+        false
+    }
+
+    fn status(&self, tetris: &Tetris) -> String {
+        format!("Zen {}", format_time(tetris.ticks()))
+    }
+
+    fn results(&self, tetris: &Tetris) -> Vec<String> {
+        vec![
+            format!("Score: {}", tetris.score),
+            format!("Lines: {}", tetris.nb_lines),
+            format!("Time: {}", format_time(tetris.ticks())),
+        ]
+    }
+}
+
+// 网格中含有垃圾格子的行数
+fn garbage_rows(tetris: &Tetris) -> usize {
+    tetris.game_map.iter().filter(|line| line.contains(&GARBAGE)).count()
+}
+
+// 挖掘模式，网格底部开始有若干行垃圾，消除所有垃圾行所用的时间
+// 无限模式下每消除一行垃圾就从底部补上一行，直到游戏结束
+pub struct Dig {
+    remaining: usize, // 网格中剩下的垃圾行数
+    cleared: usize, // 已经消除的垃圾行数
+}
+
+impl Dig {
+    pub fn new() -> Dig {
+        Dig { remaining: 0, cleared: 0 }
+    }
+}
+
+impl GameMode for Dig {
+    fn name(&self) -> &'static str {
+        "Dig"
+    }
+
+    fn start(&mut self, tetris: &mut Tetris) {
+        let rows = tetris.config().dig_rows.min(tetris.config().height);
+        tetris.add_garbage(rows);
+        self.remaining = garbage_rows(tetris);
+    }
+
+    fn tick(&mut self, tetris: &mut Tetris) -> bool {
+        let remaining = garbage_rows(tetris);
+        self.cleared += self.remaining.saturating_sub(remaining);
+        self.remaining = remaining;
+        if tetris.config().dig_infinite {
+            let missing = tetris.config().dig_rows.min(tetris.config().height).saturating_sub(remaining);
+            tetris.add_garbage(missing);
+            self.remaining += missing;
+            false
+        } else {
+            remaining == 0
+        }
+    }
+
+    fn status(&self, tetris: &Tetris) -> String {
+        format!("{} Left {}", format_time(tetris.ticks()), self.remaining)
+    }
+
+    fn results(&self, tetris: &Tetris) -> Vec<String> {
+        vec![
+            if tetris.completed { "Complete!" } else { "Game over" }.to_string(),
+            format!("Time: {}", format_time(tetris.ticks())),
+            format!("Pieces: {}", tetris.nb_pieces),
+            format!("Garbage: {}", self.cleared),
+        ]
+    }
+
+    fn save(&self) -> String {
+        format!("{} {}", self.remaining, self.cleared)
+    }
+
+    fn restore(&mut self, state: &str) -> bool {
+        let values = state.split_whitespace()
+            .map(|value| value.parse::<usize>().ok())
+            .collect::<Option<Vec<usize>>>();
+        match values.as_deref() {
+            Some(&[remaining, cleared]) => {
+                self.remaining = remaining;
+                self.cleared = cleared;
+                true
+            }
+            _ => false,
+        }
+    }
+}
+
+// 生存模式，垃圾行定时从底部升起，级别越高间隔越短，坚持的时间就是成绩
+pub struct Survival {
+    next_rise: u64, // 下一次升起垃圾行的时间，单位为微秒
+}
+
+impl Survival {
+    pub fn new() -> Survival {
+        Survival { next_rise: 0 }
+    }
+
+    fn interval(level: u32) -> u64 {
+        SURVIVAL_MICROS.saturating_sub((level.max(1) - 1) as u64 * SURVIVAL_LEVEL_MICROS)
+            .max(SURVIVAL_MIN_MICROS)
+    }
+}
+
+impl GameMode for Survival {
+    fn name(&self) -> &'static str {
+        "Survival"
+    }
+
+    fn start(&mut self, tetris: &mut Tetris) {
+        self.next_rise = Survival::interval(tetris.current_level);
+    }
+
+    fn tick(&mut self, tetris: &mut Tetris) -> bool {
+        if micros(tetris) >= self.next_rise {
+            tetris.add_garbage(1);
+            self.next_rise += Survival::interval(tetris.current_level);
+        }
+        false
+    }
+
+    fn status(&self, tetris: &Tetris) -> String {
+        let left = self.next_rise.saturating_sub(micros(tetris));
+        format!("{} ({:.1})", format_time(tetris.ticks()), left as f64 / 1_000_000.0)
+    }
+
+    fn results(&self, tetris: &Tetris) -> Vec<String> {
+        vec![
+            format!("Survived: {}", format_time(tetris.ticks())),
+            format!("Lines: {}", tetris.nb_lines),
+            format!("Level: {}", tetris.current_level),
+        ]
+    }
+
+    fn save(&self) -> String {
+        self.next_rise.to_string()
+    }
+
+    fn restore(&mut self, state: &str) -> bool {
+        match state.parse::<u64>() {
+            Ok(next_rise) => self.next_rise = next_rise,
+            Err(_) => return false,
+        }
+        true
+    }
+}
@@ -0,0 +1,82 @@
+use rand::Rng;
+
+use crate::rng::GameRng;
+
+// 垃圾行格子的颜色值，和7种方块的颜色区分开
+pub const GARBAGE: u8 = 8;
+
+// 垃圾行中空洞所在列的选择方式
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum HolePolicy {
+    Same, // 所有垃圾行的空洞在同一列
+    Random, // 每一行的空洞都随机选择
+    Messy(u32), // 每一行有百分之几的概率换到另一列
+}
+
+impl HolePolicy {
+    // 配置为 same、random 或者 messy 30
+    pub fn from_config(value: &str) -> Option<HolePolicy> {
+        let mut words = value.split_whitespace();
+        match (words.next(), words.next()) {
+            (Some("same"), None) => Some(HolePolicy::Same),
+            (Some("random"), None) => Some(HolePolicy::Random),
+            (Some("messy"), Some(percent)) => percent.parse::<u32>().ok()
+                .filter(|percent| *percent <= 100)
+                .map(HolePolicy::Messy),
+            _ => None,
+        }
+    }
+
+    pub fn to_config(self) -> String {
+        match self {
+            HolePolicy::Same => "same".to_string(),
+            HolePolicy::Random => "random".to_string(),
+            HolePolicy::Messy(percent) => format!("messy {}", percent),
+        }
+    }
+}
+
+// 生成垃圾行，记住上一行的空洞位置
+#[derive(Clone, Debug)]
+pub struct GarbageGenerator {
+    policy: HolePolicy,
+    hole: Option<usize>, // 上一行空洞所在的列
+}
+
+impl GarbageGenerator {
+    pub fn new(policy: HolePolicy) -> GarbageGenerator {
+        GarbageGenerator { policy, hole: None }
+    }
+
+    // 生成一行垃圾，除了空洞之外的格子都是GARBAGE
+    pub fn row(&mut self, width: usize, rng: &mut GameRng) -> Vec<u8> {
+        let hole = match (self.hole, self.policy) {
+            (Some(hole), HolePolicy::Same) => hole,
+            // 换列时不会随机到原来的列
+            (Some(hole), HolePolicy::Messy(percent)) if width > 1 && rng.gen_range(0, 100) < percent =>
+                (hole + rng.gen_range(1, width)) % width,
+            (Some(hole), HolePolicy::Messy(_)) => hole,
+            _ => rng.gen_range(0, width),
+        };
+        self.hole = Some(hole);
+        let mut row = vec![GARBAGE; width];
+        row[hole] = 0;
+        row
+    }
+
+    // 保存游戏时只需要记住上一行空洞的位置，还没有生成过垃圾行时为none
+    pub fn save(&self) -> String {
+        self.hole.map_or("none".to_string(), |hole| hole.to_string())
+    }
+
+    pub fn restore(&mut self, state: &str) -> bool {
+        match state {
+            "none" => self.hole = None,
+            _ => match state.parse::<usize>() {
+                Ok(hole) => self.hole = Some(hole),
+                Err(_) => return false,
+            },
+        }
+        true
+    }
+}
@@ -0,0 +1,94 @@
+// 达到20G时方块出现后直接落到底部
+pub const INSTANT_GRAVITY: f64 = 20.0;
+
+// 每个级别的下落速度，单位为G，也就是每帧(1/60秒)下落的格数
+#[derive(Clone, Debug)]
+pub enum GravityCurve {
+    Guideline, // 标准公式，每行下落的秒数为 (0.8 - (level - 1) * 0.007) ^ (level - 1)
+    Table(Vec<f64>), // 从1级开始每个级别的G，超过表中级别的使用最后一个值，0表示不会自然下落
+}
+
+impl GravityCurve {
+    // 配置为 guideline 或者用空格分开的每个级别的G
+    pub fn from_config(value: &str) -> Option<GravityCurve> {
+        if value == "guideline" {
+            return Some(GravityCurve::Guideline);
+        }
+        let table = value.split_whitespace()
+            .map(|g| g.parse::<f64>().ok().filter(|g| *g >= 0.0))
+            .collect::<Option<Vec<f64>>>()?;
+        if table.is_empty() {
+            None
+        } else {
+            Some(GravityCurve::Table(table))
+        }
+    }
+
+    pub fn to_config(&self) -> String {
+        match *self {
+            GravityCurve::Guideline => "guideline".to_string(),
+            GravityCurve::Table(ref table) => table.iter()
+                .map(|g| g.to_string())
+                .collect::<Vec<String>>().join(" "),
+        }
+    }
+
+    pub fn gravity(&self, level: u32) -> f64 {
+        let level = level.max(1);
+        let g = match *self {
+            GravityCurve::Guideline => {
+                let base = 0.8 - (level - 1) as f64 * 0.007;
+                if base <= 0.0 {
+                    return INSTANT_GRAVITY;
+                }
+                let seconds = base.powi(level as i32 - 1);
+                1.0 / (seconds * 60.0)
+            }
+            GravityCurve::Table(ref table) => table[(level as usize - 1).min(table.len() - 1)],
+        };
+        g.min(INSTANT_GRAVITY)
+    }
+}
+
+// 升级需要消除的行数
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum LevelLines {
+    Fixed(u32), // 每级固定的行数
+    Variable, // 每级需要 5 * level 行
+    Nes, // 红白机的规则，第一次升级和开始的级别有关，之后每10行升一级
+}
+
+impl LevelLines {
+    // 配置为 fixed 10、variable 或者 nes
+    pub fn from_config(value: &str) -> Option<LevelLines> {
+        let mut words = value.split_whitespace();
+        match (words.next(), words.next()) {
+            (Some("fixed"), Some(lines)) => lines.parse::<u32>().ok()
+                .filter(|lines| *lines > 0)
+                .map(LevelLines::Fixed),
+            (Some("variable"), None) => Some(LevelLines::Variable),
+            (Some("nes"), None) => Some(LevelLines::Nes),
+            _ => None,
+        }
+    }
+
+    pub fn to_config(self) -> String {
+        match self {
+            LevelLines::Fixed(lines) => format!("fixed {}", lines),
+            LevelLines::Variable => "variable".to_string(),
+            LevelLines::Nes => "nes".to_string(),
+        }
+    }
+
+    // 从start_level开始，离开level这一级时总共需要消除的行数
+    pub fn lines_to_leave(&self, start_level: u32, level: u32) -> u32 {
+        match *self {
+            LevelLines::Fixed(lines) => (level - start_level + 1) * lines,
+            LevelLines::Variable => (start_level..=level).map(|level| 5 * level).sum(),
+            LevelLines::Nes => {
+                let first = (start_level * 10 + 10).min((start_level * 10).saturating_sub(50).max(100));
+                first + (level - start_level) * 10
+            }
+        }
+    }
+}
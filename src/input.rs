@@ -0,0 +1,167 @@
+use sdl2::event::Event;
+use sdl2::keyboard::Keycode;
+
+use std::time::Duration;
+
+use crate::replay::Replay;
+use crate::tetris::{Action, Tetris};
+
+// 按住左右键时的自动平移(DAS/ARR)，不依赖操作系统的按键重复速度
+pub struct AutoShift {
+    left_held: bool,
+    right_held: bool,
+    direction: isize, // 当前生效的方向，-1为左，1为右，0表示没有按键，后按下的方向优先
+    held_micros: u64, // 当前方向已经按住的微秒数，不按帧取整，避免DAS/ARR变慢
+    repeats: usize, // 这次按下后已经自动平移的次数
+}
+
+impl AutoShift {
+    pub fn new() -> AutoShift {
+        AutoShift {
+            left_held: false,
+            right_held: false,
+            direction: 0,
+            held_micros: 0,
+            repeats: 0,
+        }
+    }
+
+    pub fn press(&mut self, direction: isize) {
+        if direction < 0 {
+            self.left_held = true;
+        } else {
+            self.right_held = true;
+        }
+        self.start(direction);
+    }
+
+    pub fn release(&mut self, direction: isize) {
+        if direction < 0 {
+            self.left_held = false;
+        } else {
+            self.right_held = false;
+        }
+        // 松开当前方向时，如果另一个方向还按着，就重新开始另一个方向的延迟
+        if self.direction == direction {
+            if self.left_held {
+                self.start(-1);
+            } else if self.right_held {
+                self.start(1);
+            } else {
+                self.direction = 0;
+            }
+        }
+    }
+
+    fn start(&mut self, direction: isize) {
+        self.direction = direction;
+        self.held_micros = 0;
+        self.repeats = 0;
+    }
+
+    pub fn direction(&self) -> isize {
+        self.direction
+    }
+
+    // 经过dt后需要自动平移的格数，按下超过das毫秒后每arr毫秒移动一格，arr为0时直接移到底
+    pub fn shifts(&mut self, dt: Duration, das: u32, arr: u32) -> usize {
+        if self.direction == 0 {
+            return 0;
+        }
+        self.held_micros += dt.as_micros() as u64;
+        let (das, arr) = (das as u64 * 1000, arr as u64 * 1000);
+        if self.held_micros < das {
+            return 0;
+        }
+        if arr == 0 {
+            return usize::MAX;
+        }
+        let expected = ((self.held_micros - das) / arr) as usize + 1;
+        let shifts = expected - self.repeats;
+        self.repeats = expected;
+        shifts
+    }
+}
+
+// 把键盘事件转换成游戏操作，忽略操作系统的按键重复，按住的效果由自动平移和软降处理
+fn map_event(event: &Event, auto_shift: &mut AutoShift) -> Option<Action> {
+    match *event {
+        Event::KeyDown { keycode: Some(Keycode::Down), repeat: false, .. } => Some(Action::SoftDrop),
+        Event::KeyUp { keycode: Some(Keycode::Down), .. } => Some(Action::SoftDropRelease),
+        Event::KeyDown { keycode: Some(Keycode::Right), repeat: false, .. } => {
+            auto_shift.press(1);
+            Some(Action::MoveRight)
+        }
+        Event::KeyUp { keycode: Some(Keycode::Right), .. } => {
+            auto_shift.release(1);
+            None
+        }
+        Event::KeyDown { keycode: Some(Keycode::Left), repeat: false, .. } => {
+            auto_shift.press(-1);
+            Some(Action::MoveLeft)
+        }
+        Event::KeyUp { keycode: Some(Keycode::Left), .. } => {
+            auto_shift.release(-1);
+            None
+        }
+        Event::KeyDown { keycode: Some(Keycode::Up), repeat: false, .. } |
+        Event::KeyDown { keycode: Some(Keycode::X), repeat: false, .. } => Some(Action::RotateCw),
+        Event::KeyDown { keycode: Some(Keycode::Z), repeat: false, .. } => Some(Action::RotateCcw),
+        Event::KeyDown { keycode: Some(Keycode::A), repeat: false, .. } => Some(Action::Rotate180),
+        Event::KeyDown { keycode: Some(Keycode::Space), repeat: false, .. } => Some(Action::HardDrop),
+        Event::KeyDown { keycode: Some(Keycode::C), repeat: false, .. } |
+        Event::KeyDown { keycode: Some(Keycode::LShift), repeat: false, .. } => Some(Action::Hold),
+        Event::KeyDown { keycode: Some(Keycode::P), repeat: false, .. } => Some(Action::Pause),
+        _ => None,
+    }
+}
+
+// 处理这一帧的键盘事件，dt为距离上一帧的毫秒数，返回当前方块是否已经被合并到网格中
+pub fn handle_events(tetris: &mut Tetris, quit: &mut bool, dt: Duration,
+                auto_shift: &mut AutoShift, event_pump: &mut sdl2::EventPump) -> bool {
+    let mut make_permanent = false;
+    for event in event_pump.poll_iter() {
+        match event {
+            Event::Quit { .. } |
+            Event::KeyDown { keycode: Some(Keycode::Escape), .. } => {
+                *quit = true;
+                break
+            }
+            _ => {}
+        }
+        if let Some(action) = map_event(&event, auto_shift) {
+            if tetris.apply(action) && action == Action::HardDrop {
+                make_permanent = true;
+            }
+        }
+    }
+    // 按住左右键超过DAS后的自动平移，被挡住后就停下，不再执行不会生效的操作
+    let direction = auto_shift.direction();
+    let action = if direction < 0 { Action::MoveLeft } else { Action::MoveRight };
+    for _ in 0..auto_shift.shifts(dt, tetris.config().das, tetris.config().arr) {
+        if !tetris.can_move(direction) || !tetris.apply(action) {
+            break
+        }
+    }
+    make_permanent
+}
+
+// 播放录像时的按键：P或空格暂停，左右键减速和加速，暂停时按句号前进一帧
+pub fn handle_replay_events(replay: &mut Replay, tetris: &mut Tetris, quit: &mut bool,
+                event_pump: &mut sdl2::EventPump) {
+    for event in event_pump.poll_iter() {
+        match event {
+            Event::Quit { .. } |
+            Event::KeyDown { keycode: Some(Keycode::Escape), .. } => {
+                *quit = true;
+                break
+            }
+            Event::KeyDown { keycode: Some(Keycode::P), repeat: false, .. } |
+            Event::KeyDown { keycode: Some(Keycode::Space), repeat: false, .. } => replay.paused = !replay.paused,
+            Event::KeyDown { keycode: Some(Keycode::Right), .. } => replay.faster(),
+            Event::KeyDown { keycode: Some(Keycode::Left), .. } => replay.slower(),
+            Event::KeyDown { keycode: Some(Keycode::Period), .. } if replay.paused => replay.step(tetris),
+            _ => {}
+        }
+    }
+}
@@ -1,24 +1,41 @@
 extern crate sdl2;
 
+mod config;
+mod game_mode;
+mod garbage;
+mod gravity;
+mod input;
+mod randomizer;
+mod replay;
+mod rng;
 mod score_file;
+mod scoring;
+mod snapshot;
 mod tetris;
 
+use sdl2::event::Event;
 use sdl2::pixels::Color;
 use sdl2::rect::Rect;
-use sdl2::render::{Canvas, Texture, TextureCreator};
+use sdl2::render::{BlendMode, Canvas, Texture, TextureCreator};
 use sdl2::video::{Window, WindowContext};
 use sdl2::image::{LoadTexture, InitFlag};
 
-use std::time::{Duration, SystemTime};
-use std::thread::sleep;
+use std::time::Instant;
 
+use crate::config::Config;
+use crate::input::{handle_events, handle_replay_events, AutoShift};
+use crate::randomizer::RandomizerKind;
+use crate::replay::{save_replay, Replay, REPLAY_FILE};
 use crate::score_file::print_game_information;
+use crate::snapshot::{load_snapshot, save_snapshot};
 use crate::tetris::update_tetris;
-use crate::tetris::Tetris;
+use crate::tetris::{Action, GameEvent, TSpin, Tetris, TetriminoKind};
 
 
-// 一个格子的大小
+// 一个格子的大小，网格太高放不进窗口时会缩小
 const TETRIS_HEIGHT: usize = 40;
+const WINDOW_HEIGHT: u32 = 800;
+const PANEL_WIDTH: u32 = 240; // 右边显示分数和方块预览的宽度，文字每个字符12像素，能放下20个字符
 
 // 一个用来创建正方形纹理的函数
 fn create_texture_rect<'a>(canvas: &mut Canvas<Window>, 
@@ -58,10 +75,18 @@ fn display_game_information<'a>(tetris: &Tetris,
        texture_creator: &'a TextureCreator<WindowContext>,
        font: &sdl2::ttf::Font,
        start_x_point: i32) {
+     let status_text = tetris.mode().status(tetris);
      let score_text = format!("Score: {}", tetris.score);
      let lines_sent_text = format!("Lines sent: {}", tetris.nb_lines);
-     let level_text = format!("Level: {}", tetris.current_level);
+     let level_text = if tetris.paused {
+        format!("Level: {} (Paused)", tetris.current_level)
+     } else {
+        format!("Level: {}", tetris.current_level)
+     };
 
+     let status = create_texture_from_text(texture_creator, font,
+        &status_text, 255, 255, 255)
+        .expect("Cannot render text");
      let score = create_texture_from_text(&texture_creator, &font,
         &score_text, 255, 255, 255)
         .expect("Cannot render text");
@@ -72,17 +97,206 @@ fn display_game_information<'a>(tetris: &Tetris,
         &level_text, 255, 255, 255)
         .expect("Cannot render text");
      
+     canvas.copy(&status, None, get_rect_from_text(&status_text,
+        start_x_point, 30))
+          .expect("Couldn't copy text");
      canvas.copy(&score, None, get_rect_from_text(&score_text, 
         start_x_point, 75))
           .expect("Couldn't copy text");
     canvas.copy(&lines_sent, None, get_rect_from_text(&score_text,
         start_x_point, 125))
           .expect("Couldn't copy text");
-    canvas.copy(&level, None, get_rect_from_text(&score_text, 
+    canvas.copy(&level, None, get_rect_from_text(&level_text, 
        start_x_point, 160))
           .expect("Couldn't copy text");
 }
 
+// 在侧边栏绘制一个缩小的方块，格子大小为正常的一半
+fn display_mini_piece(canvas: &mut Canvas<Window>,
+       textures: &[Texture],
+       kind: TetriminoKind,
+       x: i32, y: i32) {
+    let piece = kind.create();
+    let size = TETRIS_HEIGHT as u32 / 2;
+    for (line_nb, line) in piece.states[piece.current_state as usize].iter().enumerate() {
+        for (case_nb, case) in line.iter().enumerate() {
+            if *case == 0 {
+                continue
+            }
+            canvas.copy(&textures[*case as usize - 1],
+                None,
+                Rect::new(x + case_nb as i32 * size as i32,
+                          y + line_nb as i32 * size as i32,
+                          size, size))
+                .expect("Couldn't copy texture into window");
+        }
+    }
+}
+
+// 连续消除和back-to-back只在生效的时候显示
+fn display_bonus_information(tetris: &Tetris,
+       canvas: &mut Canvas<Window>,
+       texture_creator: &TextureCreator<WindowContext>,
+       font: &sdl2::ttf::Font,
+       start_x_point: i32) {
+    let mut texts = Vec::new();
+    if let Some(combo) = tetris.combo {
+        if combo > 0 {
+            texts.push(format!("Combo: {}", combo));
+        }
+    }
+    if tetris.back_to_back {
+        texts.push("Back-to-Back".to_string());
+    }
+    for (nb, text) in texts.iter().enumerate() {
+        let texture = create_texture_from_text(texture_creator, font,
+            text, 255, 255, 255)
+            .expect("Cannot render text");
+        canvas.copy(&texture, None, get_rect_from_text(text,
+            start_x_point, 745 + nb as i32 * 25))
+            .expect("Couldn't copy text");
+    }
+}
+
+fn display_hold_piece(tetris: &Tetris,
+       canvas: &mut Canvas<Window>,
+       texture_creator: &TextureCreator<WindowContext>,
+       font: &sdl2::ttf::Font,
+       textures: &[Texture],
+       start_x_point: i32) {
+    let hold_text = "Hold:";
+    // 已经暂存过一次的时候文字变灰，表示这个方块合并前不能再暂存
+    let color = if tetris.hold_used { 128 } else { 255 };
+    let hold = create_texture_from_text(texture_creator, font,
+        hold_text, color, color, color)
+        .expect("Cannot render text");
+    canvas.copy(&hold, None, get_rect_from_text(hold_text,
+        start_x_point, 210))
+        .expect("Couldn't copy text");
+    if let Some(kind) = tetris.hold_piece {
+        display_mini_piece(canvas, textures, kind, start_x_point, 240);
+    }
+}
+
+fn display_next_pieces(tetris: &Tetris,
+       canvas: &mut Canvas<Window>,
+       texture_creator: &TextureCreator<WindowContext>,
+       font: &sdl2::ttf::Font,
+       textures: &[Texture],
+       start_x_point: i32) {
+    if tetris.next_pieces().is_empty() {
+        return
+    }
+    let next_text = "Next:";
+    let next = create_texture_from_text(texture_creator, font,
+        next_text, 255, 255, 255)
+        .expect("Cannot render text");
+    canvas.copy(&next, None, get_rect_from_text(next_text,
+        start_x_point, 300))
+        .expect("Couldn't copy text");
+    // 每个预览方块占两行小格子，再留一点间隔
+    for (nb, kind) in tetris.next_pieces().iter().enumerate() {
+        display_mini_piece(canvas, textures, *kind, start_x_point,
+            330 + nb as i32 * (TETRIS_HEIGHT as i32 + 10));
+    }
+}
+
+// 用半透明的边框画出当前方块落地的位置
+fn display_ghost_piece(tetris: &Tetris,
+       canvas: &mut Canvas<Window>,
+       ghost_y: usize,
+       grid_x: i32, grid_y: i32, cell: u32) {
+    let buffer_rows = tetris.config().buffer_rows;
+    if let Some(ref piece) = tetris.current_piece {
+        canvas.set_blend_mode(BlendMode::Blend);
+        canvas.set_draw_color(Color::RGBA(255, 255, 255, 128));
+        for (line_nb, line) in piece.states[piece.current_state as usize].iter().enumerate() {
+            for (case_nb, case) in line.iter().enumerate() {
+                if *case == 0 || ghost_y + line_nb < buffer_rows {
+                    continue
+                }
+                canvas.draw_rect(Rect::new(grid_x + (piece.x + case_nb as isize) as i32 * cell as i32,
+                                           grid_y + (ghost_y + line_nb - buffer_rows) as i32 * cell as i32,
+                                           cell, cell))
+                    .expect("Couldn't draw ghost piece");
+            }
+        }
+        canvas.set_blend_mode(BlendMode::None);
+    }
+}
+
+// 提示文字显示的时间
+const MESSAGE_MILLIS: u128 = 2000;
+
+// 需要在侧边栏提示玩家的事件
+fn event_message(event: &GameEvent) -> Option<String> {
+    match *event {
+        GameEvent::LinesCleared(ref rows) if rows.len() == 4 => Some("Tetris!".to_string()),
+        GameEvent::TSpin(tspin, lines) => {
+            let name = if tspin == TSpin::Mini { "Mini T-Spin" } else { "T-Spin" };
+            let lines_name = ["", "\nSingle", "\nDouble", "\nTriple"];
+            Some(format!("{}{}", name, lines_name[(lines as usize).min(3)]))
+        }
+        GameEvent::PerfectClear(_) => Some("PERFECT\nCLEAR".to_string()),
+        GameEvent::LevelUp(level) => Some(format!("Level {}!", level)),
+        _ => None,
+    }
+}
+
+fn display_message(message: &str,
+       canvas: &mut Canvas<Window>,
+       texture_creator: &TextureCreator<WindowContext>,
+       font: &sdl2::ttf::Font,
+       start_x_point: i32) {
+    // 侧边栏比较窄，提示文字可以分成多行
+    for (line_nb, line) in message.lines().enumerate() {
+        let text = create_texture_from_text(texture_creator, font,
+            line, 255, 220, 69)
+            .expect("Cannot render text");
+        canvas.copy(&text, None, get_rect_from_text(line,
+            start_x_point, 680 + line_nb as i32 * 30))
+            .expect("Couldn't copy text");
+    }
+}
+
+// 播放录像时在侧边栏最上面显示播放状态
+fn display_replay_status(status: &str,
+       canvas: &mut Canvas<Window>,
+       texture_creator: &TextureCreator<WindowContext>,
+       font: &sdl2::ttf::Font,
+       start_x_point: i32) {
+    let text = create_texture_from_text(texture_creator, font,
+        status, 39, 218, 225)
+        .expect("Cannot render text");
+    canvas.copy(&text, None, get_rect_from_text(status,
+        start_x_point, 5))
+        .expect("Couldn't copy text");
+}
+
+// 游戏结束后的结果画面，由游戏模式决定显示的内容
+fn display_results(tetris: &Tetris,
+       canvas: &mut Canvas<Window>,
+       texture_creator: &TextureCreator<WindowContext>,
+       font: &sdl2::ttf::Font,
+       width: u32) {
+    canvas.set_draw_color(Color::RGB(0, 0, 0));
+    canvas.clear();
+    let mut lines = vec![tetris.mode().name().to_string()];
+    lines.extend(tetris.mode().results(tetris));
+    lines.push("Press any key".to_string());
+    for (line_nb, line) in lines.iter().enumerate() {
+        // 结果画面的文字比侧边栏大，水平居中
+        let (w, h) = (line.len() as u32 * 20, 34);
+        let gray = if line_nb == lines.len() - 1 { 128 } else { 255 };
+        let text = create_texture_from_text(texture_creator, font,
+            line, gray, gray, gray)
+            .expect("Cannot render text");
+        canvas.copy(&text, None, Rect::new((width as i32 - w as i32) / 2,
+            150 + line_nb as i32 * 60, w, h))
+            .expect("Couldn't copy text");
+    }
+}
+
 fn main() {
     // 初始化sdl
     let sdl_context = sdl2::init().expect("SDL Init failed");
@@ -96,10 +310,64 @@ fn main() {
     let mut font = ttf_context.load_font("res/font/Bitter-Regular.ttf", 60).expect("Couldn't load the font");  
     font.set_style(sdl2::ttf::FontStyle::NORMAL);
 
-    let width = 600;
-    let height = 800;
+    let mut config = Config::load();
+    let mut args = std::env::args().skip(1).collect::<Vec<String>>();
+    // --slot N 选择这一次使用的存档位，覆盖配置文件中的save_slot，例如 rtetris --slot 2
+    if let Some(pos) = args.iter().position(|arg| arg == "--slot") {
+        match args.get(pos + 1).and_then(|slot| slot.parse::<u32>().ok()) {
+            Some(slot) => config.save_slot = slot,
+            None => {
+                println!("Usage: --slot <number>");
+                return
+            }
+        }
+        args.drain(pos..pos + 2);
+    }
+    // 可以通过命令行参数选择方块生成器和随机种子，例如 rtetris tgm 42
+    if let Some(randomizer) = args.first()
+                    .and_then(|name| RandomizerKind::from_name(name)) {
+        config.randomizer = randomizer;
+    }
+    // rtetris replay [文件] 播放录像，没有指定文件时播放上一局
+    let mut replay = None;
+    if args.first().map(String::as_str) == Some("replay") {
+        let file_name = args.get(1).cloned().unwrap_or_else(|| REPLAY_FILE.to_string());
+        match Replay::load(&file_name) {
+            Some(loaded) => replay = Some(loaded),
+            None => {
+                println!("Couldn't load replay: {}", file_name);
+                return
+            }
+        }
+    }
+    // 上次退出时保存了游戏就从存档位继续，继续时先暂停，暂停也是一个操作，会记录到录像中
+    let mut tetris = if let Some(ref replay) = replay {
+        replay.start()
+    } else if let Some(mut tetris) = load_snapshot(config.save_slot) {
+        println!("Resuming game from slot {}", config.save_slot);
+        if !tetris.paused {
+            tetris.apply(Action::Pause);
+        }
+        tetris
+    } else {
+        match args.get(1).and_then(|seed| seed.parse::<u64>().ok()) {
+            Some(seed) => Tetris::with_seed(&config, seed),
+            None => Tetris::new(&config),
+        }
+    };
+
+    // 网格的位置和大小由配置的行数和列数决定
+    let board_width = tetris.config().width as u32;
+    let board_height = tetris.config().height as u32;
+    let cell = (TETRIS_HEIGHT as u32).min((WINDOW_HEIGHT - 80) / board_height);
+    let grid_width = cell * board_width;
+    let grid_height = cell * board_height;
     let grid_x = 20; // 地图开始的左边距
-    let grid_y = (height - TETRIS_HEIGHT as u32 * 16) as i32 / 2; // 地图开始的顶部位置
+    let grid_y = (WINDOW_HEIGHT - grid_height) as i32 / 2; // 地图开始的顶部位置
+    let panel_x = grid_x + grid_width as i32 + 20; // 侧边栏开始的位置
+    let buffer_rows = tetris.config().buffer_rows; // 网格最上面看不到的行数
+    let width = panel_x as u32 + PANEL_WIDTH;
+    let height = WINDOW_HEIGHT;
     // 获取窗口，并设置窗口的属性，整个屏幕居中，使用opengl渲染
     let window = video_subsystem.window("rust-sdl2 demo: Video", width, height)
                     .position_centered()
@@ -115,26 +383,28 @@ fn main() {
     // 获取画布的纹理创建者
     let texture_creator: TextureCreator<_> = canvas.texture_creator();
 
-    let mut tetris = Tetris::new();
-    let mut timer = SystemTime::now();
+    let mut last_frame = Instant::now();
+    let mut message: Option<(String, Instant)> = None; // 提示文字和开始显示的时间
+    let mut auto_shift = AutoShift::new();
     
     // 背景图片
     let image_texture = texture_creator.load_texture("res/images/flower.jpeg").expect("Failed to load image");
     // 游戏地图背景
-    let grid = create_texture_rect(&mut canvas, &texture_creator, 0, 0, 0 , TETRIS_HEIGHT as u32 * 10).expect("Failed to create a texture");
+    let grid = create_texture_rect(&mut canvas, &texture_creator, 0, 0, 0 , grid_width).expect("Failed to create a texture");
     // 边框为10像素
-    let border = create_texture_rect(&mut canvas, &texture_creator, 255,255, 255, TETRIS_HEIGHT as u32 * 10 + 20).expect("Failed to create a texture");
+    let border = create_texture_rect(&mut canvas, &texture_creator, 255,255, 255, grid_width + 20).expect("Failed to create a texture");
     // 使用宏简化代码
     macro_rules! texture {
         ($r:expr, $g:expr, $b:expr) => (
             create_texture_rect(&mut canvas, &texture_creator, 
-                $r, $g, $b, TETRIS_HEIGHT as u32).unwrap()
+                $r, $g, $b, cell).unwrap()
         )
     }
-    // 7种纹理方块，对应每个块的颜色
+    // 7种纹理方块，对应每个块的颜色，最后一个是垃圾行的灰色
     let textures = [texture!(255, 69, 69), texture!(255, 220, 69), texture!(237, 150, 37), 
                                         texture!(171, 99, 237), texture!(77, 149, 239), 
-                                        texture!(39, 218, 225), texture!(45, 216, 47)];
+                                        texture!(39, 218, 225), texture!(45, 216, 47),
+                                        texture!(128, 128, 128)];
 
     // 事件句柄
     let mut event_pump = sdl_context.event_pump().expect("Failed to get SDL event pump");
@@ -143,44 +413,68 @@ fn main() {
     canvas.clear();
 
     loop {
-        // 处理下落逻辑数据
-        tetris::falling(&mut tetris, &mut timer);
+        // 按真实经过的时间推进游戏逻辑，游戏内部使用固定的时间步长，不受帧率影响
+        let now = Instant::now();
+        let dt = now - last_frame;
+        last_frame = now;
+        match replay {
+            Some(ref mut replay) => replay.update(&mut tetris, dt),
+            None => tetris.update(dt),
+        }
 
         canvas.copy(&image_texture, None, None).expect("Couldn't copy texture into window");
         // 纹理会被拉伸以适应目标区域大小
         canvas.copy(&border,
         None,
-        Rect::new(10,
-                  (height - TETRIS_HEIGHT as u32 * 16) as i32 / 2 - 10, // 垂直居中
-                  TETRIS_HEIGHT as u32 * 10 + 20, TETRIS_HEIGHT as u32 * 16 + 20))
+        Rect::new(grid_x - 10,
+                  grid_y - 10, // 垂直居中
+                  grid_width + 20, grid_height + 20))
         .expect("Couldn't copy texture into window");    
         // 游戏区域的黑色背景，用来擦除刷新
         canvas.copy(&grid,
             None,
-            Rect::new(20,(height - TETRIS_HEIGHT as u32 * 16) as i32 / 2,TETRIS_HEIGHT as u32 * 10, TETRIS_HEIGHT as u32 * 16))
+            Rect::new(grid_x, grid_y, grid_width, grid_height))
                  .expect("Couldn't copy texture into window");
-        // 如果当前块已经被合并了，创建新一个新的方块开始下落
-        if !update_tetris(&mut tetris) {
+        // 如果当前块已经被合并了，创建新一个新的方块开始下落，播放录像时由录像推进游戏，也不记录分数
+        if replay.is_some() {
+            if tetris.game_over {
+                break
+            }
+        } else if !update_tetris(&mut tetris) {
+            save_replay(&tetris, REPLAY_FILE);
             break
         }
 
         let mut quit = false;
         // 处理按键事件，如果按键事件导致方块合并到了网格地图中，就不需要绘制下落的方块了，否则还需要绘制下落的方块
-        if !tetris::handle_events(&mut tetris, &mut quit, &mut timer, &mut event_pump) {
+        let hard_dropped = match replay {
+            Some(ref mut replay) => {
+                handle_replay_events(replay, &mut tetris, &mut quit, &mut event_pump);
+                false
+            }
+            None => handle_events(&mut tetris, &mut quit, dt, &mut auto_shift, &mut event_pump),
+        };
+        if !hard_dropped {
+            // 先画出方块落地的位置，再画方块本身，两者重叠时方块盖住影子
+            if tetris.config().ghost {
+                if let Some(ghost_y) = tetris.ghost_y() {
+                    display_ghost_piece(&tetris, &mut canvas, ghost_y, grid_x, grid_y, cell);
+                }
+            }
             if let Some(ref mut piece) = tetris.current_piece {
                 for (line_nb, line) in piece.states[piece.current_state as usize].iter().enumerate() {
                     for (case_nb, case) in line.iter().enumerate() {
-                        // 如果块的状态的格子为0，说明是空的，不用绘制
-                        if *case == 0 {
+                        // 如果块的状态的格子为0，说明是空的，不用绘制，在缓冲区中的格子也看不到
+                        if *case == 0 || piece.y + line_nb < buffer_rows {
                             continue
                         }
                         // 绘制当前移动的块的一个格子，case为块中的数字，用来选择用那种颜色
                         canvas.copy(&textures[*case as usize - 1],
                                 None,
-                                Rect::new(grid_x + (piece.x + case_nb as isize) as i32 * TETRIS_HEIGHT as i32,
-                                             grid_y + (piece.y + line_nb) as i32 * TETRIS_HEIGHT as i32,
-                                         TETRIS_HEIGHT as u32, 
-                                                TETRIS_HEIGHT as u32)
+                                Rect::new(grid_x + (piece.x + case_nb as isize) as i32 * cell as i32,
+                                             grid_y + (piece.y + line_nb - buffer_rows) as i32 * cell as i32,
+                                         cell, 
+                                                cell)
                                     ).expect("Couldn't copy texture into window");
                     }
                 }
@@ -188,32 +482,70 @@ fn main() {
         }
 
         if quit {  
-            print_game_information(&tetris);          
-            break
+            if replay.is_some() {
+                return
+            }
+            // 退出时保存游戏，下次启动时继续，不保存时才记录分数
+            save_replay(&tetris, REPLAY_FILE);
+            if config.save_slot > 0 && save_snapshot(&tetris, config.save_slot) {
+                println!("Game suspended to slot {}", config.save_slot);
+            } else {
+                print_game_information(&tetris);
+            }
+            return
         }
        
-        display_game_information(&tetris, &mut canvas, &texture_creator, &font, TETRIS_HEIGHT as i32 * 10 + 40);
+        if let Some(ref replay) = replay {
+            display_replay_status(&replay.status(&tetris), &mut canvas, &texture_creator, &font, panel_x);
+        }
+        display_game_information(&tetris, &mut canvas, &texture_creator, &font, panel_x);
+        display_hold_piece(&tetris, &mut canvas, &texture_creator, &font, &textures, panel_x);
+        display_next_pieces(&tetris, &mut canvas, &texture_creator, &font, &textures, panel_x);
+        display_bonus_information(&tetris, &mut canvas, &texture_creator, &font, panel_x);
+
+        // 处理这一帧的游戏事件，后发生的提示覆盖前面的
+        for event in tetris.drain_events() {
+            if let Some(text) = event_message(&event) {
+                message = Some((text, Instant::now()));
+            }
+        }
+        if let Some((ref text, shown_at)) = message {
+            if shown_at.elapsed().as_millis() < MESSAGE_MILLIS {
+                display_message(text, &mut canvas, &texture_creator, &font, panel_x);
+            } else {
+                message = None;
+            }
+        }
 
         // 绘制地图中所有非0的格子，即已经合并过的，这里面没有正在移动的块，正在移动的块还没合并到地图里面
-        for (line_nb, line) in tetris.game_map.iter().enumerate() {
+        // 缓冲区中的格子不绘制
+        for (line_nb, line) in tetris.game_map.iter().skip(buffer_rows).enumerate() {
             for (case_nb, case) in line.iter().enumerate() {
                if *case == 0 {
                   continue
                }
                canvas.copy(&textures[*case as usize - 1],
                   None,
-                  Rect::new(grid_x + case_nb as i32 * TETRIS_HEIGHT as i32,
-                  grid_y + line_nb as i32 * TETRIS_HEIGHT as i32,
-                  TETRIS_HEIGHT as u32, TETRIS_HEIGHT as u32))
+                  Rect::new(grid_x + case_nb as i32 * cell as i32,
+                  grid_y + line_nb as i32 * cell as i32,
+                  cell, cell))
                   .expect("Couldn't copy texture into window");
             }
         }
                
-        // 更新窗口显示
+        // 更新窗口显示，开启了垂直同步，显示时会等待刷新，不需要再sleep
         canvas.present();
+    }
 
-        // 每1秒60帧执行这个循环，所以要没1/60秒就sleep一下
-        sleep(Duration::new(0, 1_000_000_000u32/60));
+    // 丢掉结束前还没处理的按键，等待新的按键再关闭结果画面
+    for _ in event_pump.poll_iter() {}
+    loop {
+        display_results(&tetris, &mut canvas, &texture_creator, &font, width);
+        canvas.present();
+        match event_pump.wait_event() {
+            Event::Quit { .. } | Event::KeyDown { repeat: false, .. } => break,
+            _ => {}
+        }
     }
 }
 
@@ -0,0 +1,256 @@
+use rand::Rng;
+
+use crate::rng::GameRng;
+use crate::tetris::{kinds_from_string, kinds_to_string, TetriminoKind, TETRIMINO_KINDS};
+
+// 方块生成器，决定下一个出现的是哪种方块
+// 随机数由游戏传入，这样同一个种子总是得到相同的方块序列
+pub trait Randomizer {
+    fn next(&mut self, rng: &mut GameRng) -> TetriminoKind;
+    // 保存游戏时把内部状态转换成一行文字，恢复时再读回来
+    fn save(&self) -> String;
+    fn restore(&mut self, state: &str) -> bool;
+}
+
+// 可以在创建游戏时选择的生成器
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum RandomizerKind {
+    Bag7,
+    Bag14,
+    Tgm,
+    Nes,
+    Uniform,
+}
+
+impl RandomizerKind {
+    pub fn from_name(name: &str) -> Option<RandomizerKind> {
+        match name {
+            "bag7" => Some(RandomizerKind::Bag7),
+            "bag14" => Some(RandomizerKind::Bag14),
+            "tgm" => Some(RandomizerKind::Tgm),
+            "nes" => Some(RandomizerKind::Nes),
+            "uniform" => Some(RandomizerKind::Uniform),
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            RandomizerKind::Bag7 => "bag7",
+            RandomizerKind::Bag14 => "bag14",
+            RandomizerKind::Tgm => "tgm",
+            RandomizerKind::Nes => "nes",
+            RandomizerKind::Uniform => "uniform",
+        }
+    }
+
+    pub fn create(self) -> Box<dyn Randomizer> {
+        match self {
+            RandomizerKind::Bag7 => Box::new(BagRandomizer::new(1)),
+            RandomizerKind::Bag14 => Box::new(BagRandomizer::new(2)),
+            RandomizerKind::Tgm => Box::new(TgmRandomizer::new()),
+            RandomizerKind::Nes => Box::new(NesRandomizer::new()),
+            RandomizerKind::Uniform => Box::new(UniformRandomizer),
+        }
+    }
+}
+
+fn random_kind(rng: &mut GameRng) -> TetriminoKind {
+    TETRIMINO_KINDS[rng.gen_range(0, TETRIMINO_KINDS.len())]
+}
+
+// 把7种方块各放若干个到袋子里打乱，取完后再装一袋
+pub struct BagRandomizer {
+    copies: usize, // 每种方块在袋子里的数量，1为7-bag，2为14-bag
+    bag: Vec<TetriminoKind>,
+}
+
+impl BagRandomizer {
+    pub fn new(copies: usize) -> BagRandomizer {
+        BagRandomizer {
+            copies,
+            bag: Vec::new(),
+        }
+    }
+}
+
+impl Randomizer for BagRandomizer {
+    fn next(&mut self, rng: &mut GameRng) -> TetriminoKind {
+        if self.bag.is_empty() {
+            for _ in 0..self.copies {
+                self.bag.extend_from_slice(&TETRIMINO_KINDS);
+            }
+            rng.shuffle(&mut self.bag);
+        }
+        self.bag.pop().unwrap()
+    }
+
+    fn save(&self) -> String {
+        kinds_to_string(&self.bag)
+    }
+
+    fn restore(&mut self, state: &str) -> bool {
+        match kinds_from_string(state) {
+            Some(bag) => self.bag = bag,
+            None => return false,
+        }
+        true
+    }
+}
+
+// TGM的算法，记住最近4个方块，随机到历史中的方块就重新随机，最多重试6次
+pub struct TgmRandomizer {
+    history: [TetriminoKind; 4],
+    first: bool,
+}
+
+const TGM_ROLLS: usize = 6;
+
+impl TgmRandomizer {
+    pub fn new() -> TgmRandomizer {
+        TgmRandomizer {
+            history: [TetriminoKind::Z, TetriminoKind::S, TetriminoKind::S, TetriminoKind::Z],
+            first: true,
+        }
+    }
+}
+
+impl Randomizer for TgmRandomizer {
+    fn next(&mut self, rng: &mut GameRng) -> TetriminoKind {
+        let mut kind = random_kind(rng);
+        if self.first {
+            // 第一个方块不会是S、Z或者O，避免开局就留下空洞
+            self.first = false;
+            while kind == TetriminoKind::S || kind == TetriminoKind::Z || kind == TetriminoKind::O {
+                kind = random_kind(rng);
+            }
+        } else {
+            for _ in 1..TGM_ROLLS {
+                if !self.history.contains(&kind) {
+                    break
+                }
+                kind = random_kind(rng);
+            }
+        }
+        self.history.rotate_right(1);
+        self.history[0] = kind;
+        kind
+    }
+
+    // 历史中的4个方块和是否为第一个方块
+    fn save(&self) -> String {
+        format!("{} {}", kinds_to_string(&self.history), self.first)
+    }
+
+    fn restore(&mut self, state: &str) -> bool {
+        let mut words = state.split_whitespace();
+        let history = words.next().and_then(kinds_from_string);
+        let first = words.next().and_then(|first| first.parse::<bool>().ok());
+        match (history, first) {
+            (Some(history), Some(first)) if history.len() == self.history.len() => {
+                self.history.copy_from_slice(&history);
+                self.first = first;
+                true
+            }
+            _ => false,
+        }
+    }
+}
+
+// 红白机的算法，和上一个相同或者随机到第8个值时重新随机一次
+pub struct NesRandomizer {
+    prev: Option<TetriminoKind>,
+}
+
+impl NesRandomizer {
+    pub fn new() -> NesRandomizer {
+        NesRandomizer { prev: None }
+    }
+}
+
+impl Randomizer for NesRandomizer {
+    fn next(&mut self, rng: &mut GameRng) -> TetriminoKind {
+        let rand_nb = rng.gen_range(0, TETRIMINO_KINDS.len() + 1);
+        let kind = if rand_nb == TETRIMINO_KINDS.len() || Some(TETRIMINO_KINDS[rand_nb]) == self.prev {
+            random_kind(rng)
+        } else {
+            TETRIMINO_KINDS[rand_nb]
+        };
+        self.prev = Some(kind);
+        kind
+    }
+
+    // 没有上一个方块时为空
+    fn save(&self) -> String {
+        kinds_to_string(self.prev.as_slice())
+    }
+
+    fn restore(&mut self, state: &str) -> bool {
+        match kinds_from_string(state) {
+            Some(ref prev) if prev.len() <= 1 => self.prev = prev.first().copied(),
+            _ => return false,
+        }
+        true
+    }
+}
+
+// 完全随机
+pub struct UniformRandomizer;
+
+impl Randomizer for UniformRandomizer {
+    fn next(&mut self, rng: &mut GameRng) -> TetriminoKind {
+        random_kind(rng)
+    }
+
+    fn save(&self) -> String {
+        String::new()
+    }
+
+    fn restore(&mut self, state: &str) -> bool {
+        state.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 每一袋中每种方块出现的次数都等于copies
+    fn check_bags(kind: RandomizerKind, copies: usize) {
+        let mut randomizer = kind.create();
+        let mut rng = GameRng::new(&[42]);
+        for _ in 0..10 {
+            let mut counts = [0; 7];
+            for _ in 0..TETRIMINO_KINDS.len() * copies {
+                let piece = randomizer.next(&mut rng);
+                counts[TETRIMINO_KINDS.iter().position(|kind| *kind == piece).unwrap()] += 1;
+            }
+            assert_eq!(counts, [copies; 7]);
+        }
+    }
+
+    #[test]
+    fn bag7_deals_each_piece_once_per_bag() {
+        check_bags(RandomizerKind::Bag7, 1);
+    }
+
+    #[test]
+    fn bag14_deals_each_piece_twice_per_bag() {
+        check_bags(RandomizerKind::Bag14, 2);
+    }
+
+    #[test]
+    fn bag_restores_remaining_pieces() {
+        let mut randomizer = BagRandomizer::new(1);
+        let mut rng = GameRng::new(&[42]);
+        for _ in 0..3 {
+            randomizer.next(&mut rng);
+        }
+        let mut restored = BagRandomizer::new(1);
+        assert!(restored.restore(&randomizer.save()));
+        for _ in 0..4 {
+            assert_eq!(restored.next(&mut rng), randomizer.next(&mut rng));
+        }
+        assert!(!restored.restore("TX"));
+    }
+}
@@ -0,0 +1,208 @@
+use std::fs;
+use std::time::Duration;
+
+use crate::config::Config;
+use crate::tetris::{action_from_string, Action, Tetris, TICK_MICROS};
+
+// 录像格式的版本，保存的内容改变时增加
+const REPLAY_VERSION: u32 = 1;
+const REPLAY_HEADER: &str = "rtetris replay";
+const ACTIONS_SECTION: &str = "[actions]";
+pub const REPLAY_FILE: &str = "replay.txt";
+// 逐帧播放时每次前进1/60秒
+const FRAMES_PER_SECOND: u64 = 60;
+// 可以选择的播放速度，默认为原速
+const SPEEDS: [f64; 7] = [0.125, 0.25, 0.5, 1.0, 2.0, 4.0, 8.0];
+const NORMAL_SPEED: usize = 3;
+
+// 录像的内容，第一行是格式版本，然后是游戏的配置、种子和总的tick数，[actions]之后每行一个操作
+// 相同的配置、种子和操作总是得到相同的游戏
+fn replay_text(tetris: &Tetris) -> String {
+    let mut lines = vec![format!("{} {}", REPLAY_HEADER, REPLAY_VERSION)];
+    lines.extend(tetris.config().save());
+    lines.push(format!("seed = {}", tetris.seed()));
+    lines.push(format!("ticks = {}", tetris.ticks()));
+    lines.push(ACTIONS_SECTION.to_string());
+    for &(tick, action) in tetris.actions() {
+        lines.push(format!("{} {}", tick, action.name()));
+    }
+    lines.join("\n") + "\n"
+}
+
+pub fn save_replay(tetris: &Tetris, file_name: &str) -> bool {
+    fs::write(file_name, replay_text(tetris)).is_ok()
+}
+
+// 播放录像，用录下的操作代替键盘控制游戏
+pub struct Replay {
+    config: Config,
+    seed: u64,
+    ticks: u64, // 录像结束时的tick数
+    actions: Vec<(u64, Action)>,
+    next: usize, // 下一个要执行的操作
+    pending_micros: u64, // 还不够一个tick的时间
+    steps: u64, // 逐帧前进过的帧数
+    speed: usize, // 播放速度在SPEEDS中的位置
+    pub paused: bool,
+}
+
+impl Replay {
+    pub fn load(file_name: &str) -> Option<Replay> {
+        Replay::parse(&fs::read_to_string(file_name).ok()?)
+    }
+
+    fn parse(content: &str) -> Option<Replay> {
+        let (header, content) = content.split_once('\n')?;
+        if header != format!("{} {}", REPLAY_HEADER, REPLAY_VERSION) {
+            println!("Unsupported replay version: {}", header);
+            return None;
+        }
+        let mut config = Config::default();
+        let mut seed = None;
+        let mut ticks = None;
+        let mut actions = Vec::new();
+        let mut in_actions = false;
+        for line in content.lines() {
+            if line == ACTIONS_SECTION {
+                in_actions = true;
+            } else if in_actions {
+                actions.push(action_from_string(line)?);
+            } else {
+                let (key, value) = line.split_once('=')?;
+                let (key, value) = (key.trim(), value.trim());
+                if key == "seed" {
+                    seed = Some(value.parse::<u64>().ok()?);
+                } else if key == "ticks" {
+                    ticks = Some(value.parse::<u64>().ok()?);
+                } else if !config.set(key, value) {
+                    return None;
+                }
+            }
+        }
+        Some(Replay {
+            config,
+            seed: seed?,
+            ticks: ticks?,
+            actions,
+            next: 0,
+            pending_micros: 0,
+            steps: 0,
+            speed: NORMAL_SPEED,
+            paused: false,
+        })
+    }
+
+    // 用录像的配置和种子创建游戏
+    pub fn start(&self) -> Tetris {
+        Tetris::with_seed(&self.config, self.seed)
+    }
+
+    // 按经过的时间和播放速度推进游戏
+    pub fn update(&mut self, tetris: &mut Tetris, dt: Duration) {
+        if self.paused {
+            return;
+        }
+        self.advance(tetris, (dt.as_micros() as f64 * SPEEDS[self.speed]) as u64);
+    }
+
+    // 暂停时前进一帧，第n帧结束在n/60秒，按两帧结束时间的差前进，累计起来不会有误差
+    pub fn step(&mut self, tetris: &mut Tetris) {
+        let end = |steps: u64| steps * 1_000_000 / FRAMES_PER_SECOND;
+        self.steps += 1;
+        self.advance(tetris, end(self.steps) - end(self.steps - 1));
+    }
+
+    // 和游戏中一样累计时间，不足一个tick的部分留到下一次
+    fn advance(&mut self, tetris: &mut Tetris, micros: u64) {
+        self.pending_micros += micros;
+        while self.pending_micros >= TICK_MICROS {
+            self.pending_micros -= TICK_MICROS;
+            self.tick(tetris);
+        }
+    }
+
+    // 先执行在这个tick之前发生的操作，再推进一个tick，到了录像结束的时间就不再推进
+    // 游戏暂停时tick数不变，暂停和继续的操作会在同一个tick中一起执行
+    fn tick(&mut self, tetris: &mut Tetris) {
+        while let Some(&(tick, action)) = self.actions.get(self.next) {
+            if tick > tetris.ticks() {
+                break
+            }
+            tetris.apply(action);
+            self.next += 1;
+        }
+        if tetris.ticks() < self.ticks {
+            tetris.tick();
+        }
+    }
+
+    // 所有操作都执行完，并且到了录像结束的时间
+    pub fn finished(&self, tetris: &Tetris) -> bool {
+        self.next >= self.actions.len() && tetris.ticks() >= self.ticks
+    }
+
+    pub fn faster(&mut self) {
+        self.speed = (self.speed + 1).min(SPEEDS.len() - 1);
+    }
+
+    pub fn slower(&mut self) {
+        self.speed = self.speed.saturating_sub(1);
+    }
+
+    pub fn status(&self, tetris: &Tetris) -> String {
+        if self.finished(tetris) {
+            "Replay (End)".to_string()
+        } else if self.paused {
+            "Replay (Paused)".to_string()
+        } else {
+            format!("Replay x{}", SPEEDS[self.speed])
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 回放只在整数tick上推进，比较状态时不算不足一个tick的时间
+    fn state(tetris: &Tetris) -> Vec<String> {
+        tetris.save().into_iter().filter(|line| !line.starts_with("pending_micros")).collect()
+    }
+
+    #[test]
+    fn replay_matches_live_game_at_game_over() {
+        let actions = [
+            Action::MoveLeft, Action::RotateCw, Action::Hold, Action::MoveRight, Action::SoftDrop,
+            Action::Rotate180, Action::SoftDropRelease, Action::RotateCcw, Action::MoveLeft,
+        ];
+        let mut live = Tetris::with_seed(&Config::default(), 7);
+        let mut frame = 0;
+        while !live.game_over {
+            live.apply(actions[frame % actions.len()]);
+            if frame % 5 == 4 {
+                live.apply(Action::HardDrop);
+            }
+            live.update(Duration::from_micros(1_000_000 / FRAMES_PER_SECOND));
+            frame += 1;
+        }
+
+        let mut replay = Replay::parse(&replay_text(&live)).unwrap();
+        let mut tetris = replay.start();
+        while !tetris.game_over && !replay.finished(&tetris) {
+            replay.tick(&mut tetris);
+        }
+        assert!(tetris.game_over);
+        assert_eq!(state(&tetris), state(&live));
+    }
+
+    #[test]
+    fn sixty_steps_advance_one_second() {
+        let mut replay = Replay::parse(&replay_text(&Tetris::with_seed(&Config::default(), 7))).unwrap();
+        replay.ticks = u64::MAX;
+        let mut tetris = replay.start();
+        for _ in 0..FRAMES_PER_SECOND {
+            replay.step(&mut tetris);
+        }
+        assert_eq!(tetris.ticks() * TICK_MICROS, 1_000_000);
+    }
+}
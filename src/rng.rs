@@ -0,0 +1,42 @@
+use rand::{Rng, SeedableRng, StdRng};
+
+// 记录已经取了多少个随机数的生成器
+// StdRng的内部状态不能直接保存，恢复时用同一个种子重新取同样多的随机数
+pub struct GameRng {
+    rng: StdRng,
+    draws: u64,
+}
+
+impl GameRng {
+    pub fn new(seed: &[usize]) -> GameRng {
+        GameRng {
+            rng: StdRng::from_seed(seed),
+            draws: 0,
+        }
+    }
+
+    pub fn restore(seed: &[usize], draws: u64) -> GameRng {
+        let mut rng = GameRng::new(seed);
+        for _ in 0..draws {
+            rng.next_u64();
+        }
+        rng
+    }
+
+    pub fn draws(&self) -> u64 {
+        self.draws
+    }
+}
+
+// 64位平台上StdRng取32位和64位的随机数都会用掉一个64位的值，所以两种都只算一次
+impl Rng for GameRng {
+    fn next_u32(&mut self) -> u32 {
+        self.draws += 1;
+        self.rng.next_u32()
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        self.draws += 1;
+        self.rng.next_u64()
+    }
+}
@@ -1,6 +1,6 @@
 use std::fs::File;
 use std::io::{self, Read, Write};
-use crate::tetris::Tetris;
+use crate::tetris::{Tetris, TICK_MICROS};
 
 const NB_HIGHSCORES: usize = 3;
 const HIGHSCORE_FILE: &'static str = "save.txt";
@@ -78,6 +78,7 @@ pub fn print_game_information(tetris: &Tetris) {
         save_highscores_and_lines(&[tetris.score], &[tetris.nb_lines]);
     }
     println!("Game over...");
+    println!("Mode:            {}", tetris.mode().name());
     println!("Score:           {}{}",
              tetris.score,
              if new_highest_highscore { " [NEW HIGHSCORE]"} else { "" });
@@ -85,4 +86,6 @@ pub fn print_game_information(tetris: &Tetris) {
              tetris.nb_lines,
              if new_highest_lines_sent { " [NEW HIGHLINES]"} else { "" });
     println!("Current level:   {}", tetris.current_level);
+    println!("Time:            {:.1}s", tetris.ticks() as f64 * TICK_MICROS as f64 / 1_000_000.0);
+    println!("Seed:            {}", tetris.seed());
 }
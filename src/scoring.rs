@@ -0,0 +1,187 @@
+use crate::tetris::TSpin;
+
+// 一个方块合并后用来计算分数的信息
+pub struct LockResult {
+    pub lines: u32, // 这次消除的行数
+    pub level: u32, // 消除前的级别
+    pub tspin: TSpin,
+    pub combo: u32, // 这次消除前已经连续消除的次数
+    pub back_to_back: bool, // 这次消除是否有back-to-back奖励
+    pub perfect_clear: bool, // 消除后整个网格都空了
+}
+
+// 计分规则，方块合并、软降和硬降时分别计算得分
+pub trait ScoringRule {
+    fn lock(&self, result: &LockResult) -> u32;
+    fn soft_drop(&self, cells: u32) -> u32;
+    fn hard_drop(&self, cells: u32) -> u32;
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum ScoringKind {
+    Guideline,
+    Legacy,
+}
+
+impl ScoringKind {
+    pub fn from_name(name: &str) -> Option<ScoringKind> {
+        match name {
+            "guideline" => Some(ScoringKind::Guideline),
+            "legacy" => Some(ScoringKind::Legacy),
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            ScoringKind::Guideline => "guideline",
+            ScoringKind::Legacy => "legacy",
+        }
+    }
+
+    pub fn create(self) -> Box<dyn ScoringRule> {
+        match self {
+            ScoringKind::Guideline => Box::new(GuidelineScoring),
+            ScoringKind::Legacy => Box::new(LegacyScoring),
+        }
+    }
+}
+
+// 标准规则，消除1到4行分别为100/300/500/800乘以级别，软降每格1分，硬降每格2分
+pub struct GuidelineScoring;
+
+const LINE_POINTS: [u32; 5] = [0, 100, 300, 500, 800];
+// T旋消除0到3行的分数
+const TSPIN_POINTS: [u32; 4] = [400, 800, 1200, 1600];
+const TSPIN_MINI_POINTS: [u32; 4] = [100, 200, 400, 400];
+// 全消时消除1到4行的额外分数，back-to-back的连消4行全消为3200
+const PERFECT_CLEAR_POINTS: [u32; 5] = [0, 800, 1200, 1800, 2000];
+const PERFECT_CLEAR_B2B_TETRIS_POINTS: u32 = 3200;
+
+impl ScoringRule for GuidelineScoring {
+    fn lock(&self, result: &LockResult) -> u32 {
+        let lines = result.lines as usize;
+        let mut points = match result.tspin {
+            TSpin::None => LINE_POINTS[lines.min(4)],
+            TSpin::Mini => TSPIN_MINI_POINTS[lines.min(3)],
+            TSpin::Full => TSPIN_POINTS[lines.min(3)],
+        } * result.level;
+        // back-to-back的消除分数乘以1.5
+        if result.back_to_back {
+            points = points * 3 / 2;
+        }
+        if result.perfect_clear {
+            points += if result.lines == 4 && result.back_to_back {
+                PERFECT_CLEAR_B2B_TETRIS_POINTS
+            } else {
+                PERFECT_CLEAR_POINTS[lines.min(4)]
+            } * result.level;
+        }
+        // 连续消除每次额外加50乘以连续次数和级别
+        points + 50 * result.combo * result.level
+    }
+
+    fn soft_drop(&self, cells: u32) -> u32 {
+        cells
+    }
+
+    fn hard_drop(&self, cells: u32) -> u32 {
+        cells * 2
+    }
+}
+
+// 原来的规则，每次合并和每消除一行都加上当前级别，连消4行再加1000，T旋、连续消除、back-to-back和全消都没有额外的分数
+pub struct LegacyScoring;
+
+impl ScoringRule for LegacyScoring {
+    fn lock(&self, result: &LockResult) -> u32 {
+        let mut points = result.level + result.lines * result.level;
+        if result.lines == 4 {
+            // A "tetris"!
+            points += 1000;
+        }
+        points
+    }
+
+    fn soft_drop(&self, _cells: u32) -> u32 {
+        0
+    }
+
+    fn hard_drop(&self, _cells: u32) -> u32 {
+        0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn result(lines: u32, level: u32, tspin: TSpin) -> LockResult {
+        LockResult {
+            lines,
+            level,
+            tspin,
+            combo: 0,
+            back_to_back: false,
+            perfect_clear: false,
+        }
+    }
+
+    #[test]
+    fn guideline_line_clears() {
+        let scoring = GuidelineScoring;
+        let points: Vec<_> = (0..=4).map(|lines| scoring.lock(&result(lines, 1, TSpin::None))).collect();
+        assert_eq!(points, [0, 100, 300, 500, 800]);
+        assert_eq!(scoring.lock(&result(4, 3, TSpin::None)), 2400);
+    }
+
+    #[test]
+    fn guideline_tspins() {
+        let scoring = GuidelineScoring;
+        let full: Vec<_> = (0..=3).map(|lines| scoring.lock(&result(lines, 1, TSpin::Full))).collect();
+        assert_eq!(full, [400, 800, 1200, 1600]);
+        let mini: Vec<_> = (0..=2).map(|lines| scoring.lock(&result(lines, 2, TSpin::Mini))).collect();
+        assert_eq!(mini, [200, 400, 800]);
+    }
+
+    #[test]
+    fn guideline_combo_and_back_to_back() {
+        let scoring = GuidelineScoring;
+        let mut tetris = result(4, 2, TSpin::None);
+        tetris.back_to_back = true;
+        assert_eq!(scoring.lock(&tetris), 2400);
+        let mut single = result(1, 2, TSpin::None);
+        single.combo = 3;
+        assert_eq!(scoring.lock(&single), 200 + 300);
+        // 没有消除时combo为0，不加分
+        assert_eq!(scoring.lock(&result(0, 2, TSpin::None)), 0);
+    }
+
+    #[test]
+    fn guideline_perfect_clear() {
+        let scoring = GuidelineScoring;
+        let mut single = result(1, 1, TSpin::None);
+        single.perfect_clear = true;
+        assert_eq!(scoring.lock(&single), 100 + 800);
+        let mut tetris = result(4, 1, TSpin::None);
+        tetris.perfect_clear = true;
+        assert_eq!(scoring.lock(&tetris), 800 + 2000);
+        tetris.back_to_back = true;
+        assert_eq!(scoring.lock(&tetris), 1200 + 3200);
+    }
+
+    #[test]
+    fn guideline_drops() {
+        let scoring = GuidelineScoring;
+        assert_eq!(scoring.soft_drop(5), 5);
+        assert_eq!(scoring.hard_drop(5), 10);
+    }
+
+    #[test]
+    fn legacy_scoring() {
+        let scoring = LegacyScoring;
+        assert_eq!(scoring.lock(&result(0, 2, TSpin::Full)), 2);
+        assert_eq!(scoring.lock(&result(4, 2, TSpin::None)), 1010);
+        assert_eq!(scoring.hard_drop(5), 0);
+    }
+}
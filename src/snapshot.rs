@@ -0,0 +1,104 @@
+use std::fs;
+
+use crate::config::Config;
+use crate::tetris::Tetris;
+
+// 存档格式的版本，保存的内容改变时增加，旧版本的存档不能继续
+const SNAPSHOT_VERSION: u32 = 1;
+const SNAPSHOT_HEADER: &str = "rtetris snapshot";
+const STATE_SECTION: &str = "[state]";
+
+fn snapshot_file(slot: u32) -> String {
+    format!("suspend{}.txt", slot)
+}
+
+// 存档的内容，第一行是格式版本，然后是游戏的配置，[state]之后是游戏状态
+fn snapshot_text(tetris: &Tetris) -> String {
+    let mut lines = vec![format!("{} {}", SNAPSHOT_HEADER, SNAPSHOT_VERSION)];
+    lines.extend(tetris.config().save());
+    lines.push(STATE_SECTION.to_string());
+    lines.extend(tetris.save());
+    lines.join("\n") + "\n"
+}
+
+pub fn save_snapshot(tetris: &Tetris, slot: u32) -> bool {
+    fs::write(snapshot_file(slot), snapshot_text(tetris)).is_ok()
+}
+
+fn parse_snapshot(content: &str) -> Option<Tetris> {
+    let (header, content) = content.split_once('\n')?;
+    if header != format!("{} {}", SNAPSHOT_HEADER, SNAPSHOT_VERSION) {
+        println!("Unsupported snapshot version: {}", header);
+        return None;
+    }
+    let mut config = Config::default();
+    let mut state = Vec::new();
+    let mut in_state = false;
+    for line in content.lines() {
+        if line == STATE_SECTION {
+            in_state = true;
+            continue
+        }
+        let (key, value) = line.split_once('=')?;
+        let (key, value) = (key.trim(), value.trim());
+        if in_state {
+            state.push((key, value));
+        } else if !config.set(key, value) {
+            return None;
+        }
+    }
+    Tetris::restore(&config, &state)
+}
+
+// 从存档位继续游戏，存档只能继续一次，恢复成功后才删除，读不了的存档留着不动
+pub fn load_snapshot(slot: u32) -> Option<Tetris> {
+    let file_name = snapshot_file(slot);
+    let content = fs::read_to_string(&file_name).ok()?;
+    match parse_snapshot(&content) {
+        Some(tetris) => {
+            let _ = fs::remove_file(&file_name);
+            Some(tetris)
+        }
+        None => {
+            println!("Ignoring snapshot that couldn't be restored: {}", file_name);
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tetris::Action;
+    use std::time::Duration;
+
+    #[test]
+    fn snapshot_round_trip() {
+        let mut config = Config::default();
+        assert!(config.set("width", "12") && config.set("randomizer", "tgm"));
+        let mut tetris = Tetris::with_seed(&config, 5);
+        for action in [Action::MoveLeft, Action::HardDrop, Action::Hold, Action::RotateCw, Action::HardDrop] {
+            tetris.apply(action);
+            tetris.update(Duration::from_micros(16_667));
+        }
+        let text = snapshot_text(&tetris);
+        let mut restored = parse_snapshot(&text).unwrap();
+        assert_eq!(snapshot_text(&restored), text);
+
+        // 恢复后继续游戏，和没有保存过的游戏一样
+        for action in [Action::MoveRight, Action::HardDrop, Action::Hold] {
+            tetris.apply(action);
+            restored.apply(action);
+        }
+        tetris.update(Duration::from_secs(2));
+        restored.update(Duration::from_secs(2));
+        assert_eq!(snapshot_text(&restored), snapshot_text(&tetris));
+    }
+
+    #[test]
+    fn snapshot_with_other_version_is_rejected() {
+        let text = snapshot_text(&Tetris::with_seed(&Config::default(), 5));
+        let text = text.replacen(&format!("{} {}", SNAPSHOT_HEADER, SNAPSHOT_VERSION), &format!("{} 0", SNAPSHOT_HEADER), 1);
+        assert!(parse_snapshot(&text).is_none());
+    }
+}
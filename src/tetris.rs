@@ -1,43 +1,164 @@
 extern crate rand;
 
-use std::time::SystemTime;
-use sdl2::event::Event;
-use sdl2::keyboard::Keycode;
+use std::collections::{HashMap, VecDeque};
+use std::str::FromStr;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
+use crate::config::{Config, LockReset};
+use crate::game_mode::GameMode;
+use crate::garbage::{GarbageGenerator, GARBAGE};
+use crate::gravity::INSTANT_GRAVITY;
+use crate::randomizer::Randomizer;
+use crate::rng::GameRng;
 use crate::score_file::print_game_information;
+use crate::scoring::{LockResult, ScoringRule};
 
-const LEVEL_TIMES: [u32; 10] = [1000, 850, 700, 600, 500, 400, 300, 250, 221, 190];
-const LEVEL_LINES: [u32; 10] = [5,   40,  60,  80,  100, 120, 140, 160, 180, 200];
+// 游戏逻辑按固定的时间步长运行，一个tick为1毫秒，和画面的帧率无关
+pub const TICK_MICROS: u64 = 1000;
+// 一个tick相当于多少帧，下落速度G是按每秒60帧定义的
+const FRAMES_PER_TICK: f64 = TICK_MICROS as f64 * 60.0 / 1_000_000.0;
 
 type Piece = Vec<Vec<u8>>; // 表示一种二维图形
 type States = Vec<Piece>;
 
+// SRS踢墙表，按顺时针的状态转换 0->R, R->2, 2->L, L->0 排列
+// 表中的y向上为正，和网格的y方向相反
+const JLSTZ_KICKS: [[(isize, isize); 5]; 4] = [
+    [(0, 0), (-1, 0), (-1, 1), (0, -2), (-1, -2)],
+    [(0, 0), (1, 0), (1, -1), (0, 2), (1, 2)],
+    [(0, 0), (1, 0), (1, 1), (0, -2), (1, -2)],
+    [(0, 0), (-1, 0), (-1, -1), (0, 2), (-1, 2)],
+];
+// 长条I使用单独的踢墙表
+const I_KICKS: [[(isize, isize); 5]; 4] = [
+    [(0, 0), (-2, 0), (1, 0), (-2, -1), (1, 2)],
+    [(0, 0), (-1, 0), (2, 0), (-1, 2), (2, -1)],
+    [(0, 0), (2, 0), (-1, 0), (2, 1), (-1, -2)],
+    [(0, 0), (1, 0), (-2, 0), (1, -2), (-2, 1)],
+];
+// 180度旋转的踢墙表，按 0->2, R->L, 2->0, L->R 排列，y同样向上为正
+const HALF_KICKS: [[(isize, isize); 6]; 4] = [
+    [(0, 0), (0, 1), (1, 1), (-1, 1), (1, 0), (-1, 0)],
+    [(0, 0), (1, 0), (1, 2), (1, 1), (0, 2), (0, 1)],
+    [(0, 0), (0, -1), (-1, -1), (1, -1), (-1, 0), (1, 0)],
+    [(0, 0), (-1, 0), (-1, 2), (-1, 1), (0, 2), (0, 1)],
+];
+// 方块O旋转后形状不变，不需要踢墙
+const O_KICKS: [(isize, isize); 1] = [(0, 0)];
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum TetriminoKind {
+    I, J, L, O, S, Z, T,
+}
+
+pub const TETRIMINO_KINDS: [TetriminoKind; 7] = [
+    TetriminoKind::I, TetriminoKind::J, TetriminoKind::L, TetriminoKind::O,
+    TetriminoKind::S, TetriminoKind::Z, TetriminoKind::T,
+];
+
+impl TetriminoKind {
+    // 创建这种类型的方块
+    pub fn create(self) -> Tetrimino {
+        match self {
+            TetriminoKind::I => TetriminoI::new(),
+            TetriminoKind::J => TetriminoJ::new(),
+            TetriminoKind::L => TetriminoL::new(),
+            TetriminoKind::O => TetriminoO::new(),
+            TetriminoKind::S => TetriminoS::new(),
+            TetriminoKind::Z => TetriminoZ::new(),
+            TetriminoKind::T => TetriminoT::new(),
+        }
+    }
+
+    pub fn name(self) -> char {
+        match self {
+            TetriminoKind::I => 'I',
+            TetriminoKind::J => 'J',
+            TetriminoKind::L => 'L',
+            TetriminoKind::O => 'O',
+            TetriminoKind::S => 'S',
+            TetriminoKind::Z => 'Z',
+            TetriminoKind::T => 'T',
+        }
+    }
+
+    pub fn from_name(name: char) -> Option<TetriminoKind> {
+        TETRIMINO_KINDS.iter().copied().find(|kind| kind.name() == name)
+    }
+}
+
+// 一串方块写成字母，例如 "TSZ"，保存游戏时使用
+pub fn kinds_to_string(kinds: &[TetriminoKind]) -> String {
+    kinds.iter().map(|kind| kind.name()).collect()
+}
+
+pub fn kinds_from_string(text: &str) -> Option<Vec<TetriminoKind>> {
+    text.chars().map(TetriminoKind::from_name).collect()
+}
+
 pub struct Tetrimino {
+    pub kind: TetriminoKind,
     pub states: States,
     pub x: isize,  // 方块的坐标位置
     pub y: usize,
-    pub current_state: u8, // 当前是哪一种状态，例如长条I有两种
+    pub current_state: u8, // 当前是哪一种状态，按SRS顺序为 0, R, 2, L
+    pub last_rotation: Option<(Action, usize)>, // 最后一次成功的操作如果是旋转，记录旋转操作和使用的踢墙偏移
 }
 
 impl Tetrimino {
-    fn rotate(&mut self, game_map: &[Vec<u8>]) {
+    // 顺时针旋转的踢墙偏移
+    fn kicks(&self, from_state: u8) -> &'static [(isize, isize)] {
+        match self.kind {
+            TetriminoKind::I => &I_KICKS[from_state as usize],
+            TetriminoKind::O => &O_KICKS,
+            _ => &JLSTZ_KICKS[from_state as usize],
+        }
+    }
+
+    // 旋转成功时返回使用的是踢墙表中的第几个偏移
+    fn rotate(&mut self, game_map: &[Vec<u8>]) -> Option<usize> {
         // 旋转就认为时状态的变化
-        let mut tmp_state = self.current_state + 1;
-        // 状态不能超过最大情况
-        if tmp_state as usize >= self.states.len() {
-            tmp_state = 0;
-        }
-        // 在水平方向尝试能不能找到合适的文位置，简化游戏
-        let x_pos = [0, -1, 1, -2, 2, -3];
-        for x in x_pos.iter() {
-            if self.test_position(game_map, tmp_state as usize,
-                                  self.x + x, self.y) == true {
+        let tmp_state = (self.current_state + 1) % self.states.len() as u8;
+        let kicks = self.kicks(self.current_state);
+        self.try_rotate(game_map, tmp_state, kicks, 1)
+    }
+
+    // 逆时针旋转，A->B的偏移就是B->A顺时针偏移取反
+    fn rotate_ccw(&mut self, game_map: &[Vec<u8>]) -> Option<usize> {
+        let len = self.states.len() as u8;
+        let tmp_state = (self.current_state + len - 1) % len;
+        let kicks = self.kicks(tmp_state);
+        self.try_rotate(game_map, tmp_state, kicks, -1)
+    }
+
+    fn rotate_180(&mut self, game_map: &[Vec<u8>]) -> Option<usize> {
+        let tmp_state = (self.current_state + 2) % self.states.len() as u8;
+        let kicks: &[(isize, isize)] = match self.kind {
+            TetriminoKind::O => &O_KICKS,
+            _ => &HALF_KICKS[self.current_state as usize],
+        };
+        self.try_rotate(game_map, tmp_state, kicks, 1)
+    }
+
+    // 按踢墙表依次尝试偏移，第一个不冲突的位置就是旋转后的位置
+    fn try_rotate(&mut self, game_map: &[Vec<u8>], tmp_state: u8,
+                  kicks: &[(isize, isize)], sign: isize) -> Option<usize> {
+        for (kick, (dx, dy)) in kicks.iter().enumerate() {
+            let x = self.x + dx * sign;
+            let y = self.y as isize - dy * sign;
+            if y < 0 {
+                continue
+            }
+            if self.test_position(game_map, tmp_state as usize, x, y as usize) {
                 self.current_state = tmp_state; // 如果不冲突，就可以切换为这个形状
-                self.x += *x;
-                break
+                self.x = x;
+                self.y = y as usize;
+                return Some(kick);
             }
         }
+        None
     }
+
     // 检测与网格中的其他元素是否冲突
     fn test_position(&self, game_map: &[Vec<u8>],
                      tmp_state: usize, x: isize, y: usize) -> bool {
@@ -48,7 +169,7 @@ impl Tetrimino {
                 if self.states[tmp_state][shift_y][shift_x as usize] != 0 && // 方块中这个格子不为0
                     (y + shift_y >= game_map.len() || // y 方向没有超过网格的高度
                      x < 0 ||
-                     x as usize >= game_map[y + shift_y].len() || // 没有超过行的最大宽度10
+                     x as usize >= game_map[y + shift_y].len() || // 没有超过行的最大宽度
                      game_map[y + shift_y][x as usize] != 0) { // 和地图网格的当前位置的格子不冲突
                     return false;
                 }
@@ -61,11 +182,21 @@ impl Tetrimino {
         self.test_position(game_map, self.current_state as usize, self.x, self.y)
     }
 
+    // 方块从当前位置直接下落，最终停下来的y坐标
+    pub fn landing_y(&self, game_map: &[Vec<u8>]) -> usize {
+        let mut y = self.y;
+        while self.test_position(game_map, self.current_state as usize, self.x, y + 1) {
+            y += 1;
+        }
+        y
+    }
+
     // 移动方块的位置
     fn change_position(&mut self, game_map: &[Vec<u8>], new_x: isize, new_y: usize) -> bool {
         if self.test_position(game_map, self.current_state as usize, new_x, new_y) == true {
             self.x = new_x as isize;
             self.y = new_y;
+            self.last_rotation = None;
             true
         } else {
             false
@@ -78,13 +209,22 @@ trait TetriminoGenerator {
 }
 
 struct TetriminoI;
-// 数字1表示颜色
+// 数字1表示颜色，四个状态按SRS的 0, R, 2, L 顺序排列
 impl TetriminoGenerator for TetriminoI {
     fn new() -> Tetrimino {
         Tetrimino {
-            states: vec![vec![vec![1, 1, 1, 1],
+            kind: TetriminoKind::I,
+            states: vec![vec![vec![0, 0, 0, 0],
+                              vec![1, 1, 1, 1],
                               vec![0, 0, 0, 0],
+                              vec![0, 0, 0, 0]],
+                         vec![vec![0, 0, 1, 0],
+                              vec![0, 0, 1, 0],
+                              vec![0, 0, 1, 0],
+                              vec![0, 0, 1, 0]],
+                         vec![vec![0, 0, 0, 0],
                               vec![0, 0, 0, 0],
+                              vec![1, 1, 1, 1],
                               vec![0, 0, 0, 0]],
                          vec![vec![0, 1, 0, 0],
                               vec![0, 1, 0, 0],
@@ -93,6 +233,7 @@ impl TetriminoGenerator for TetriminoI {
             x: 4,
             y: 0,
             current_state: 0,
+            last_rotation: None,
         }
     }
 }
@@ -102,25 +243,27 @@ struct TetriminoJ;
 impl TetriminoGenerator for TetriminoJ {
     fn new() -> Tetrimino {
         Tetrimino {
-            states: vec![vec![vec![2, 2, 2, 0],
-                              vec![2, 0, 0, 0],
+            kind: TetriminoKind::J,
+            states: vec![vec![vec![2, 0, 0, 0],
+                              vec![2, 2, 2, 0],
                               vec![0, 0, 0, 0],
                               vec![0, 0, 0, 0]],
-                         vec![vec![2, 2, 0, 0],
+                         vec![vec![0, 2, 2, 0],
                               vec![0, 2, 0, 0],
                               vec![0, 2, 0, 0],
                               vec![0, 0, 0, 0]],
-                         vec![vec![0, 0, 2, 0],
+                         vec![vec![0, 0, 0, 0],
                               vec![2, 2, 2, 0],
-                              vec![0, 0, 0, 0],
+                              vec![0, 0, 2, 0],
                               vec![0, 0, 0, 0]],
-                         vec![vec![2, 0, 0, 0],
-                              vec![2, 0, 0, 0],
+                         vec![vec![0, 2, 0, 0],
+                              vec![0, 2, 0, 0],
                               vec![2, 2, 0, 0],
                               vec![0, 0, 0, 0]]],
             x: 4,
             y: 0,
             current_state: 0,
+            last_rotation: None,
         }
     }
 }
@@ -130,25 +273,27 @@ struct TetriminoL;
 impl TetriminoGenerator for TetriminoL {
     fn new() -> Tetrimino {
         Tetrimino {
-            states: vec![vec![vec![3, 3, 3, 0],
-                              vec![0, 0, 3, 0],
+            kind: TetriminoKind::L,
+            states: vec![vec![vec![0, 0, 3, 0],
+                              vec![3, 3, 3, 0],
                               vec![0, 0, 0, 0],
                               vec![0, 0, 0, 0]],
                          vec![vec![0, 3, 0, 0],
                               vec![0, 3, 0, 0],
-                              vec![3, 3, 0, 0],
+                              vec![0, 3, 3, 0],
                               vec![0, 0, 0, 0]],
-                         vec![vec![3, 0, 0, 0],
+                         vec![vec![0, 0, 0, 0],
                               vec![3, 3, 3, 0],
-                              vec![0, 0, 0, 0],
+                              vec![3, 0, 0, 0],
                               vec![0, 0, 0, 0]],
                          vec![vec![3, 3, 0, 0],
-                              vec![3, 0, 0, 0],
-                              vec![3, 0, 0, 0],
+                              vec![0, 3, 0, 0],
+                              vec![0, 3, 0, 0],
                               vec![0, 0, 0, 0]]],
             x: 4,
             y: 0,
             current_state: 0,
+            last_rotation: None,
         }
     }
 }
@@ -158,13 +303,27 @@ struct TetriminoO;
 impl TetriminoGenerator for TetriminoO {
     fn new() -> Tetrimino {
         Tetrimino {
-            states: vec![vec![vec![4, 4, 0, 0],
-                              vec![4, 4, 0, 0],
+            kind: TetriminoKind::O,
+            states: vec![vec![vec![0, 4, 4, 0],
+                              vec![0, 4, 4, 0],
+                              vec![0, 0, 0, 0],
+                              vec![0, 0, 0, 0]],
+                         vec![vec![0, 4, 4, 0],
+                              vec![0, 4, 4, 0],
+                              vec![0, 0, 0, 0],
+                              vec![0, 0, 0, 0]],
+                         vec![vec![0, 4, 4, 0],
+                              vec![0, 4, 4, 0],
+                              vec![0, 0, 0, 0],
+                              vec![0, 0, 0, 0]],
+                         vec![vec![0, 4, 4, 0],
+                              vec![0, 4, 4, 0],
                               vec![0, 0, 0, 0],
                               vec![0, 0, 0, 0]]],
-            x: 5,
+            x: 4,
             y: 0,
             current_state: 0,
+            last_rotation: None,
         }
     }
 }
@@ -174,6 +333,7 @@ struct TetriminoS;
 impl TetriminoGenerator for TetriminoS {
     fn new() -> Tetrimino {
         Tetrimino {
+            kind: TetriminoKind::S,
             states: vec![vec![vec![0, 5, 5, 0],
                               vec![5, 5, 0, 0],
                               vec![0, 0, 0, 0],
@@ -181,10 +341,19 @@ impl TetriminoGenerator for TetriminoS {
                          vec![vec![0, 5, 0, 0],
                               vec![0, 5, 5, 0],
                               vec![0, 0, 5, 0],
+                              vec![0, 0, 0, 0]],
+                         vec![vec![0, 0, 0, 0],
+                              vec![0, 5, 5, 0],
+                              vec![5, 5, 0, 0],
+                              vec![0, 0, 0, 0]],
+                         vec![vec![5, 0, 0, 0],
+                              vec![5, 5, 0, 0],
+                              vec![0, 5, 0, 0],
                               vec![0, 0, 0, 0]]],
             x: 4,
             y: 0,
             current_state: 0,
+            last_rotation: None,
         }
     }
 }
@@ -194,6 +363,7 @@ struct TetriminoZ;
 impl TetriminoGenerator for TetriminoZ {
     fn new() -> Tetrimino {
         Tetrimino {
+            kind: TetriminoKind::Z,
             states: vec![vec![vec![6, 6, 0, 0],
                               vec![0, 6, 6, 0],
                               vec![0, 0, 0, 0],
@@ -201,10 +371,19 @@ impl TetriminoGenerator for TetriminoZ {
                          vec![vec![0, 0, 6, 0],
                               vec![0, 6, 6, 0],
                               vec![0, 6, 0, 0],
+                              vec![0, 0, 0, 0]],
+                         vec![vec![0, 0, 0, 0],
+                              vec![6, 6, 0, 0],
+                              vec![0, 6, 6, 0],
+                              vec![0, 0, 0, 0]],
+                         vec![vec![0, 6, 0, 0],
+                              vec![6, 6, 0, 0],
+                              vec![6, 0, 0, 0],
                               vec![0, 0, 0, 0]]],
             x: 4,
             y: 0,
             current_state: 0,
+            last_rotation: None,
         }
     }
 }
@@ -214,72 +393,656 @@ struct TetriminoT;
 impl TetriminoGenerator for TetriminoT {
     fn new() -> Tetrimino {
         Tetrimino {
-            states: vec![vec![vec![7, 7, 7, 0],
-                              vec![0, 7, 0, 0],
+            kind: TetriminoKind::T,
+            states: vec![vec![vec![0, 7, 0, 0],
+                              vec![7, 7, 7, 0],
                               vec![0, 0, 0, 0],
                               vec![0, 0, 0, 0]],
                          vec![vec![0, 7, 0, 0],
-                              vec![7, 7, 0, 0],
+                              vec![0, 7, 7, 0],
                               vec![0, 7, 0, 0],
                               vec![0, 0, 0, 0]],
-                         vec![vec![0, 7, 0, 0],
+                         vec![vec![0, 0, 0, 0],
                               vec![7, 7, 7, 0],
-                              vec![0, 0, 0, 0],
+                              vec![0, 7, 0, 0],
                               vec![0, 0, 0, 0]],
                          vec![vec![0, 7, 0, 0],
-                              vec![0, 7, 7, 0],
+                              vec![7, 7, 0, 0],
                               vec![0, 7, 0, 0],
                               vec![0, 0, 0, 0]]],
             x: 4,
             y: 0,
             current_state: 0,
+            last_rotation: None,
+        }
+    }
+}
+
+// 玩家可以对游戏做的操作，键盘、回放或者机器人都通过它来驱动游戏
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Action {
+    MoveLeft,
+    MoveRight,
+    SoftDrop, // 下降一格并开始软降
+    SoftDropRelease, // 停止软降
+    HardDrop,
+    RotateCw,
+    RotateCcw,
+    Rotate180,
+    Hold,
+    Pause, // 暂停或者继续
+}
+
+// 所有操作，按名字查找时使用
+const ACTIONS: [Action; 10] = [
+    Action::MoveLeft, Action::MoveRight, Action::SoftDrop, Action::SoftDropRelease, Action::HardDrop,
+    Action::RotateCw, Action::RotateCcw, Action::Rotate180, Action::Hold, Action::Pause,
+];
+
+impl Action {
+    pub fn name(self) -> &'static str {
+        match self {
+            Action::MoveLeft => "left",
+            Action::MoveRight => "right",
+            Action::SoftDrop => "soft_drop",
+            Action::SoftDropRelease => "soft_drop_release",
+            Action::HardDrop => "hard_drop",
+            Action::RotateCw => "cw",
+            Action::RotateCcw => "ccw",
+            Action::Rotate180 => "180",
+            Action::Hold => "hold",
+            Action::Pause => "pause",
         }
     }
+
+    pub fn from_name(name: &str) -> Option<Action> {
+        ACTIONS.iter().copied().find(|action| action.name() == name)
+    }
+}
+
+// 合并时方块T的旋转类型
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum TSpin {
+    None,
+    Mini,
+    Full,
+}
+
+// 游戏结束的原因
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum TopOut {
+    Block, // 新出现的方块和网格中的方块重叠
+    Lock, // 方块完全在可见区域上方合并
+    PartialLock, // 方块有一部分在可见区域上方合并，需要在配置中开启
+    Garbage, // 垃圾行把网格中的方块推出了顶部
+}
+
+// 游戏过程中发生的事件，前端每帧取出来处理，例如播放音效、显示提示或者统计
+#[derive(Clone, PartialEq, Debug)]
+pub enum GameEvent {
+    PieceSpawned(TetriminoKind),
+    PieceLocked(TetriminoKind),
+    LinesCleared(Vec<usize>), // 被消除的行在消除前的行号
+    TSpin(TSpin, u32), // T旋的类型和同时消除的行数
+    PerfectClear(u32), // 消除后整个网格都空了，带上消除的行数
+    LevelUp(u32), // 升级后的级别
+    GoalReached, // 达到了游戏模式的目标
+    Hold(TetriminoKind), // 被暂存的方块
+    Garbage(usize), // 从底部升起的垃圾行数
+    GameOver(TopOut),
 }
 
 pub struct Tetris {
-    pub game_map: Vec<Vec<u8>>,// 16*10的网格
+    pub game_map: Vec<Vec<u8>>,// 行数和列数由配置决定的网格，最上面的buffer_rows行是看不到的缓冲区
     pub current_level: u32,
     pub score: u32,
     pub nb_lines: u32, // 消除的总行数
+    pub nb_pieces: u32, // 已经合并的方块数
+    pub combo: Option<u32>, // 连续消除的次数，第一次消除为0，没有消除时为None
+    pub back_to_back: bool, // 上一次消除是否为连消4行或者T旋消除
     pub current_piece: Option<Tetrimino>,// 当前下落的方块
+    pub hold_piece: Option<TetriminoKind>, // 暂存的方块
+    pub hold_used: bool, // 每个方块合并前只能暂存一次
+    pub soft_drop: bool, // 是否按住了下键
+    pub paused: bool,
+    pub game_over: bool,
+    pub completed: bool, // 是否因为达到了游戏模式的目标而结束
+    events: Vec<GameEvent>, // 还没有被前端取走的事件
+    actions: Vec<(u64, Action)>, // 这一局所有的操作和发生时的tick数，用来保存录像
+    ticks: u64, // 游戏已经运行的tick数，暂停时不计
+    pending_micros: u64, // 还不够一个tick的时间
+    fall_progress: f64, // 自然下落累积的格数，满一格就下降一格
+    lock_ticks: Option<u32>, // 方块落地后开始计时，None表示还没有落地过
+    lock_resets: u32, // 当前方块已经重新计时的次数
+    lowest_y: usize, // 当前方块到达过的最低一行
+    config: Config,
+    mode: Option<Box<dyn GameMode>>, // 只有在调用模式的tick时才暂时取出来
+    randomizer: Box<dyn Randomizer>, // 决定下一个方块的生成器
+    scoring: Box<dyn ScoringRule>,
+    queue: VecDeque<TetriminoKind>, // 接下来要出现的方块
+    rng: GameRng,
+    garbage: GarbageGenerator,
+    garbage_rng: GameRng, // 垃圾行使用单独的随机数，不影响方块序列
+    seed: u64,
 } 
 
 impl Tetris {
-    pub fn new() -> Tetris {
-        // 地图大小为16行，每行10个格子
-        let mut game_map = Vec::new();
-        for _ in 0..16 {
-            game_map.push(vec![0, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
-        }
-        Tetris {
-            game_map: game_map,
-            current_level: 1, // 从1级开始
+    // 使用当前时间作为随机种子
+    pub fn new(config: &Config) -> Tetris {
+        let seed = match SystemTime::now().duration_since(UNIX_EPOCH) {
+            Ok(elapsed) => elapsed.as_secs() ^ elapsed.subsec_nanos() as u64,
+            Err(_) => 0,
+        };
+        Tetris::with_seed(config, seed)
+    }
+
+    // 相同的种子和相同的操作总是得到相同的方块序列和分数
+    pub fn with_seed(config: &Config, seed: u64) -> Tetris {
+        // 游戏模式可以调整配置
+        let mode = config.mode.create();
+        let mut config = config.clone();
+        mode.configure(&mut config);
+        // 地图大小为配置的行数加上缓冲区的行数，每行为配置的列数个格子
+        let game_map = vec![vec![0; config.width]; config.buffer_rows + config.height];
+        let mut tetris = Tetris {
+            game_map,
+            current_level: config.start_level,
             score: 0,
             nb_lines: 0,
+            nb_pieces: 0,
+            combo: None,
+            back_to_back: false,
             current_piece: None,
+            hold_piece: None,
+            hold_used: false,
+            soft_drop: false,
+            paused: false,
+            game_over: false,
+            completed: false,
+            events: Vec::new(),
+            actions: Vec::new(),
+            ticks: 0,
+            pending_micros: 0,
+            fall_progress: 0.0,
+            lock_ticks: None,
+            lock_resets: 0,
+            lowest_y: 0,
+            randomizer: config.randomizer.create(),
+            scoring: config.scoring.create(),
+            garbage: GarbageGenerator::new(config.garbage_holes),
+            config,
+            mode: Some(mode),
+            queue: VecDeque::new(),
+            rng: GameRng::new(&[seed as usize]),
+            garbage_rng: GameRng::new(&[seed as usize, 1]),
+            seed,
+        };
+        if let Some(mut mode) = tetris.mode.take() {
+            mode.start(&mut tetris);
+            tetris.mode = Some(mode);
+        }
+        tetris
+    }
+
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+
+    // 取出上次调用以来发生的所有事件
+    pub fn drain_events(&mut self) -> Vec<GameEvent> {
+        std::mem::take(&mut self.events)
+    }
+
+    pub fn ticks(&self) -> u64 {
+        self.ticks
+    }
+
+    // 按经过的时间推进游戏，不足一个tick的部分留到下一次
+    pub fn update(&mut self, dt: Duration) {
+        self.pending_micros += dt.as_micros() as u64;
+        while self.pending_micros >= TICK_MICROS {
+            self.pending_micros -= TICK_MICROS;
+            self.tick();
+        }
+    }
+
+    // 推进一个tick：需要时生成新方块，处理自然下落和锁定延迟
+    pub fn tick(&mut self) {
+        if self.paused || self.game_over {
+            return;
+        }
+        self.spawn();
+        if self.game_over {
+            return;
+        }
+        self.ticks += 1;
+        let gravity = self.gravity();
+        if let Some(ref mut piece) = self.current_piece {
+            let cells = if gravity >= INSTANT_GRAVITY {
+                // 20G时直接落到底部
+                let landing_y = piece.landing_y(&self.game_map);
+                let cells = landing_y - piece.y;
+                if cells > 0 {
+                    let x = piece.x;
+                    piece.change_position(&self.game_map, x, landing_y);
+                }
+                cells
+            } else {
+                self.fall_progress += gravity * FRAMES_PER_TICK;
+                let mut cells = 0;
+                while self.fall_progress >= 1.0 {
+                    self.fall_progress -= 1.0;
+                    let (x, y) = (piece.x, piece.y + 1);
+                    if !piece.change_position(&self.game_map, x, y) {
+                        // 落地后不再累积
+                        self.fall_progress = 0.0;
+                        break
+                    }
+                    cells += 1;
+                }
+                cells
+            };
+            // 软降时下落的格数也计分
+            if self.soft_drop && cells > 0 {
+                let points = self.scoring.soft_drop(cells as u32);
+                self.update_score(points);
+            }
+        }
+        // 不能继续下降时不会立即合并，而是等待锁定延迟
+        self.update_lock();
+        // 由游戏模式检查是否达到了目标
+        if let Some(mut mode) = self.mode.take() {
+            if mode.tick(self) && !self.game_over {
+                self.completed = true;
+                self.game_over = true;
+                self.events.push(GameEvent::GoalReached);
+            }
+            self.mode = Some(mode);
+        }
+    }
+
+    pub fn mode(&self) -> &dyn GameMode {
+        self.mode.as_deref().expect("Game mode is only taken during tick")
+    }
+
+    // 当前级别的下落速度，按住下键时按倍数加快
+    fn gravity(&self) -> f64 {
+        let gravity = self.config.gravity.gravity(self.current_level);
+        if self.soft_drop {
+            // 没有重力或者重力很小时，软降至少按1G的六十分之一加快
+            gravity.max(1.0 / 60.0) * self.config.soft_drop_factor as f64
+        } else {
+            gravity
+        }
+    }
+
+    // 当前没有方块时从队列中取出下一个，新出现的方块(包括从暂存中换出来的)就已经冲突了，说明游戏结束了
+    fn spawn(&mut self) {
+        if self.current_piece.is_none() {
+            let piece = self.create_new_tetrimino();
+            self.events.push(GameEvent::PieceSpawned(piece.kind));
+            self.current_piece = Some(piece);
+        }
+        if let Some(ref piece) = self.current_piece {
+            if !piece.test_current_position(&self.game_map) {
+                self.top_out(TopOut::Block);
+            }
+        }
+    }
+
+    fn top_out(&mut self, reason: TopOut) {
+        if self.config.top_out {
+            self.game_over = true;
+            self.events.push(GameEvent::GameOver(reason));
+        } else {
+            // 不会结束的模式清空网格继续
+            for line in self.game_map.iter_mut() {
+                for case in line.iter_mut() {
+                    *case = 0;
+                }
+            }
+        }
+    }
+
+    // 从底部升起若干行垃圾，网格中的方块整体上移，被推出顶部时游戏结束
+    // 当前方块和升起的方块重叠时向上推，推不开时也会结束游戏
+    pub fn add_garbage(&mut self, rows: usize) {
+        if rows == 0 || self.game_over {
+            return;
+        }
+        let rows = rows.min(self.game_map.len());
+        if self.game_map[..rows].iter().any(|line| line.iter().any(|case| *case != 0)) {
+            self.top_out(TopOut::Garbage);
+            if self.game_over {
+                return;
+            }
+        }
+        self.game_map.drain(..rows);
+        for _ in 0..rows {
+            let row = self.garbage.row(self.config.width, &mut self.garbage_rng);
+            self.game_map.push(row);
+        }
+        self.events.push(GameEvent::Garbage(rows));
+        let pushed = match self.current_piece {
+            Some(ref mut piece) => {
+                let (x, y) = (piece.x, piece.y);
+                piece.test_current_position(&self.game_map) ||
+                    (1..=rows.min(y)).any(|shift| piece.change_position(&self.game_map, x, y - shift))
+            }
+            None => true,
+        };
+        if !pushed {
+            self.top_out(TopOut::Block);
+        } else if let Some(ref piece) = self.current_piece {
+            // 被推上去之后重新下落到原来的行不算到达新的一行
+            self.lowest_y = self.lowest_y.min(piece.y);
+        }
+    }
+
+    // 保存游戏的全部状态，每个元素为 key = value 格式的一行，最后是网格的每一行
+    // 随机数只记录已经取过的个数，恢复时用种子重新生成
+    pub fn save(&self) -> Vec<String> {
+        let mut lines = vec![
+            format!("seed = {}", self.seed),
+            format!("draws = {}", self.rng.draws()),
+            format!("garbage_draws = {}", self.garbage_rng.draws()),
+            format!("ticks = {}", self.ticks),
+            format!("pending_micros = {}", self.pending_micros),
+            format!("fall_progress = {}", self.fall_progress),
+            format!("level = {}", self.current_level),
+            format!("score = {}", self.score),
+            format!("lines = {}", self.nb_lines),
+            format!("pieces = {}", self.nb_pieces),
+            format!("combo = {}", option_to_string(self.combo)),
+            format!("back_to_back = {}", self.back_to_back),
+            format!("piece = {}", piece_to_string(&self.current_piece)),
+            format!("hold = {}", kinds_to_string(self.hold_piece.as_slice())),
+            format!("hold_used = {}", self.hold_used),
+            format!("paused = {}", self.paused),
+            format!("lock_ticks = {}", option_to_string(self.lock_ticks)),
+            format!("lock_resets = {}", self.lock_resets),
+            format!("lowest_y = {}", self.lowest_y),
+            format!("queue = {}", self.queue.iter().map(|kind| kind.name()).collect::<String>()),
+            format!("randomizer = {}", self.randomizer.save()),
+            format!("garbage = {}", self.garbage.save()),
+            format!("mode = {}", self.mode().save()),
+        ];
+        for line in &self.game_map {
+            lines.push(format!("row = {}", line.iter().map(|case| case.to_string()).collect::<String>()));
+        }
+        // 继续游戏后保存录像时需要之前的操作
+        for &(tick, action) in &self.actions {
+            lines.push(format!("action = {} {}", tick, action.name()));
+        }
+        lines
+    }
+
+    // 用保存的配置和状态恢复游戏，状态不完整或者有错误时返回None
+    pub fn restore(config: &Config, lines: &[(&str, &str)]) -> Option<Tetris> {
+        let mut values = HashMap::new();
+        let mut rows = Vec::new();
+        let mut actions = Vec::new();
+        for &(key, value) in lines {
+            if key == "row" {
+                rows.push(value);
+            } else if key == "action" {
+                actions.push(action_from_string(value)?);
+            } else {
+                values.insert(key, value);
+            }
+        }
+        let seed = field(&values, "seed")?;
+        let mut tetris = Tetris::with_seed(config, seed);
+        tetris.rng = GameRng::restore(&[seed as usize], field(&values, "draws")?);
+        tetris.garbage_rng = GameRng::restore(&[seed as usize, 1], field(&values, "garbage_draws")?);
+        tetris.ticks = field(&values, "ticks")?;
+        tetris.pending_micros = field(&values, "pending_micros")?;
+        tetris.fall_progress = field(&values, "fall_progress")?;
+        tetris.current_level = field(&values, "level")?;
+        tetris.score = field(&values, "score")?;
+        tetris.nb_lines = field(&values, "lines")?;
+        tetris.nb_pieces = field(&values, "pieces")?;
+        tetris.combo = option_field(&values, "combo")?;
+        tetris.back_to_back = field(&values, "back_to_back")?;
+        tetris.current_piece = piece_from_string(values.get("piece")?)?;
+        tetris.hold_piece = match kinds_from_string(values.get("hold")?)?.as_slice() {
+            [] => None,
+            [kind] => Some(*kind),
+            _ => return None,
+        };
+        tetris.hold_used = field(&values, "hold_used")?;
+        tetris.paused = field(&values, "paused")?;
+        tetris.lock_ticks = option_field(&values, "lock_ticks")?;
+        tetris.lock_resets = field(&values, "lock_resets")?;
+        tetris.lowest_y = field(&values, "lowest_y")?;
+        tetris.queue = kinds_from_string(values.get("queue")?)?.into_iter().collect();
+        tetris.actions = actions;
+        if !tetris.randomizer.restore(values.get("randomizer")?) ||
+           !tetris.garbage.restore(values.get("garbage")?) ||
+           !tetris.mode.as_mut()?.restore(values.get("mode")?) {
+            return None;
+        }
+        // 网格的大小必须和配置一致
+        if rows.len() != tetris.game_map.len() {
+            return None;
+        }
+        for (line, row) in tetris.game_map.iter_mut().zip(rows) {
+            let cases = row.chars()
+                .map(|case| case.to_digit(10).map(|case| case as u8).filter(|case| *case <= GARBAGE))
+                .collect::<Option<Vec<u8>>>()?;
+            if cases.len() != line.len() {
+                return None;
+            }
+            *line = cases;
+        }
+        // 创建游戏时产生的事件不需要了，例如挖掘模式开始时的垃圾行
+        tetris.events.clear();
+        Some(tetris)
+    }
+
+    pub fn config(&self) -> &Config {
+        &self.config
+    }
+
+    // 执行一个操作，返回操作是否生效
+    // 录像中只记下改变了游戏状态的操作，暂停和继续总是会记下
+    pub fn apply(&mut self, action: Action) -> bool {
+        let (tick, soft_drop) = (self.ticks, self.soft_drop);
+        let applied = self.apply_action(action);
+        if applied || self.soft_drop != soft_drop {
+            self.actions.push((tick, action));
         }
+        applied
     }
 
-    // 随机生成一个形状
-    fn create_new_tetrimino(&self) -> Tetrimino {
-        static mut PREV: u8 = 7; // 和C++中的静态变量作用相同
-        let mut rand_nb = rand::random::<u8>() % 7;
-        // 避免生成两个相同的，因为静态变量存在多线程同时访问的问题，所以是不安全的
-        if unsafe { PREV } == rand_nb {
-            rand_nb = rand::random::<u8>() % 7;
+    fn apply_action(&mut self, action: Action) -> bool {
+        match action {
+            Action::Pause => {
+                self.paused = !self.paused;
+                return true;
+            }
+            // 暂停时也要跟着下键改变软降的状态，否则继续游戏后会一直软降，或者按着下键也不软降
+            Action::SoftDrop if !self.game_over => self.soft_drop = true,
+            Action::SoftDropRelease if !self.game_over => {
+                let released = self.soft_drop;
+                self.soft_drop = false;
+                return released;
+            }
+            _ => {}
+        }
+        if self.paused || self.game_over {
+            return false;
+        }
+        // 游戏刚开始还没有tick时先取出第一个方块，这样回放录像时和游戏中的顺序一样
+        self.spawn();
+        if self.game_over {
+            return false;
+        }
+        let game_map = &self.game_map;
+        let piece = match self.current_piece {
+            Some(ref mut piece) => piece,
+            None => return false,
+        };
+        let (x, y) = (piece.x, piece.y);
+        let moved = match action {
+            Action::MoveLeft => piece.change_position(game_map, x - 1, y),
+            Action::MoveRight => piece.change_position(game_map, x + 1, y),
+            Action::RotateCw | Action::RotateCcw | Action::Rotate180 => {
+                let kick = match action {
+                    Action::RotateCw => piece.rotate(game_map),
+                    Action::RotateCcw => piece.rotate_ccw(game_map),
+                    _ => piece.rotate_180(game_map),
+                };
+                if let Some(kick) = kick {
+                    piece.last_rotation = Some((action, kick));
+                }
+                kick.is_some()
+            }
+            Action::SoftDrop => {
+                // 落地后不会立即合并，由锁定延迟决定
+                if !piece.change_position(game_map, x, y + 1) {
+                    return false;
+                }
+                self.fall_progress = 0.0; // 重新开始下落的计时
+                let points = self.scoring.soft_drop(1);
+                self.update_score(points);
+                return true;
+            }
+            Action::HardDrop => {
+                // 快速下降到底部，不能移动了，所以直接合并到网格地图
+                let landing_y = piece.landing_y(game_map);
+                let points = self.scoring.hard_drop((landing_y - piece.y) as u32);
+                if landing_y != piece.y {
+                    piece.last_rotation = None;
+                }
+                piece.y = landing_y;
+                self.update_score(points);
+                self.make_permanent();
+                return true;
+            }
+            Action::Hold => {
+                if !self.hold() {
+                    return false;
+                }
+                self.fall_progress = 0.0;
+                return true;
+            }
+            Action::Pause | Action::SoftDropRelease => unreachable!(),
+        };
+        // 平移或旋转成功，用来重置锁定延迟
+        if moved {
+            self.on_piece_moved();
+        }
+        moved
+    }
+
+    // 当前方块能否水平移动dx格，自动平移被挡住之后就不用再尝试
+    pub fn can_move(&self, dx: isize) -> bool {
+        if self.paused || self.game_over {
+            return false;
+        }
+        match self.current_piece {
+            Some(ref piece) => piece.test_position(&self.game_map, piece.current_state as usize, piece.x + dx, piece.y),
+            None => true, // 还没有取出方块，由apply取出后再移动
+        }
+    }
+
+    pub fn actions(&self) -> &[(u64, Action)] {
+        &self.actions
+    }
+
+    // 当前方块的影子位置，也就是硬降后所在的y坐标
+    pub fn ghost_y(&self) -> Option<usize> {
+        self.current_piece.as_ref().map(|piece| piece.landing_y(&self.game_map))
+    }
+
+    // 预览中接下来的方块，第一个是下一个出现的
+    pub fn next_pieces(&self) -> &VecDeque<TetriminoKind> {
+        &self.queue
+    }
+
+    // 从队列中取出下一个形状，并由生成器补满预览队列
+    fn create_new_tetrimino(&mut self) -> Tetrimino {
+        while self.queue.len() <= self.config.preview_len {
+            let kind = self.randomizer.next(&mut self.rng);
+            self.queue.push_back(kind);
+        }
+        let kind = self.queue.pop_front().unwrap();
+        self.spawn_tetrimino(kind)
+    }
+
+    // 在可见区域上方的缓冲区中间创建方块，网格很窄时靠左放下4格宽的方块
+    // 有缓冲区时和标准规则一样，出现后如果可以就立即下降一格
+    fn spawn_tetrimino(&self, kind: TetriminoKind) -> Tetrimino {
+        let mut piece = kind.create();
+        let width = self.config.width;
+        piece.x = (width / 2 - 1).min(width - piece.states[0][0].len()) as isize;
+        piece.y = self.config.buffer_rows.saturating_sub(2);
+        if self.config.buffer_rows > 0 {
+            let (x, y) = (piece.x, piece.y + 1);
+            piece.change_position(&self.game_map, x, y);
+        }
+        piece
+    }
+
+    // 把当前方块和暂存的方块交换，暂存为空时从队列中取下一个方块
+    fn hold(&mut self) -> bool {
+        if self.hold_used {
+            return false;
+        }
+        if let Some(piece) = self.current_piece.take() {
+            self.hold_used = true;
+            self.events.push(GameEvent::Hold(piece.kind));
+            // 换出来的方块也是新出现的方块
+            if let Some(kind) = self.hold_piece.replace(piece.kind) {
+                self.events.push(GameEvent::PieceSpawned(kind));
+                self.current_piece = Some(self.spawn_tetrimino(kind));
+            }
+            self.reset_lock();
+            self.spawn();
+            true
+        } else {
+            false
         }
-        unsafe { PREV = rand_nb; }
+    }
+
+    fn reset_lock(&mut self) {
+        self.lock_ticks = None;
+        self.lock_resets = 0;
+        self.lowest_y = 0;
+    }
 
-        match rand_nb {
-            0 => TetriminoI::new(),
-            1 => TetriminoJ::new(),
-            2 => TetriminoL::new(),
-            3 => TetriminoO::new(),
-            4 => TetriminoS::new(),
-            5 => TetriminoZ::new(),
-            6 => TetriminoT::new(),
-            _ => unreachable!(),
+    // 方块平移或旋转成功后调用，move模式下落地的方块会重新计时
+    fn on_piece_moved(&mut self) {
+        if self.config.lock_reset == LockReset::Move && self.lock_ticks.is_some() &&
+           self.lock_resets < self.config.lock_resets {
+            self.lock_ticks = None;
+            self.lock_resets += 1;
+        }
+    }
+
+    // 处理锁定延迟，方块在地面上停留的时间超过延迟就合并到网格
+    fn update_lock(&mut self) {
+        let grounded = match self.current_piece {
+            Some(ref piece) => {
+                // 下降到新的一行，两种模式都会重新计时
+                if piece.y > self.lowest_y {
+                    self.lowest_y = piece.y;
+                    self.lock_ticks = None;
+                    self.lock_resets = 0;
+                }
+                !piece.test_position(&self.game_map, piece.current_state as usize, piece.x, piece.y + 1)
+            }
+            None => return,
+        };
+        if !grounded {
+            return;
+        }
+        // 累加在地面上停留的tick数，超过延迟就合并
+        let lock_ticks = self.lock_ticks.map_or(1, |ticks| ticks + 1);
+        self.lock_ticks = Some(lock_ticks);
+        if lock_ticks >= self.config.lock_delay {
+            self.make_permanent();
         }
     }
 
@@ -289,18 +1052,56 @@ impl Tetris {
 
     fn increase_level(&mut self) {
         self.current_level += 1;
+        self.events.push(GameEvent::LevelUp(self.current_level));
     }
     // 消除的行数超过当前级别的行数要求后，级别增加一级
     fn increase_line(&mut self) {
         self.nb_lines += 1;
-        if self.nb_lines > LEVEL_LINES[self.current_level as usize - 1] {
+        if self.nb_lines >= self.config.level_lines.lines_to_leave(self.config.start_level, self.current_level) {
             self.increase_level();
         }
     }
 
+    // 三角规则判断T旋：最后一次操作是旋转，T中心周围的四个角有三个被占据(墙和地面也算)
+    // 朝向的一侧两个角都被占据是T旋，否则是迷你T旋，使用SRS第5个踢墙偏移时也算作T旋
+    fn detect_tspin(&self, piece: &Tetrimino) -> TSpin {
+        let (action, kick) = match piece.last_rotation {
+            Some(rotation) if piece.kind == TetriminoKind::T => rotation,
+            _ => return TSpin::None,
+        };
+        let occupied = |dx: isize, dy: isize| {
+            let x = piece.x + dx;
+            let y = piece.y + dy as usize;
+            y >= self.game_map.len() || x < 0 || x as usize >= self.game_map[y].len() ||
+                self.game_map[y][x as usize] != 0
+        };
+        let corners = [(0, 0), (2, 0), (2, 2), (0, 2)];
+        if corners.iter().filter(|(dx, dy)| occupied(*dx, *dy)).count() < 3 {
+            return TSpin::None;
+        }
+        // 按 0, R, 2, L 的朝向，T尖端一侧的两个角
+        let (front_a, front_b) = match piece.current_state {
+            0 => (corners[0], corners[1]),
+            1 => (corners[1], corners[2]),
+            2 => (corners[2], corners[3]),
+            _ => (corners[3], corners[0]),
+        };
+        if (occupied(front_a.0, front_a.1) && occupied(front_b.0, front_b.1)) ||
+           (kick == 4 && action != Action::Rotate180) {
+            TSpin::Full
+        } else {
+            TSpin::Mini
+        }
+    }
+
     // 把一个块合并地图网格中
     fn make_permanent(&mut self) {
-        let mut to_add = 0;
+        let tspin = match self.current_piece {
+            Some(ref piece) => self.detect_tspin(piece),
+            None => TSpin::None,
+        };
+        let mut visible = false; // 有格子合并在可见区域
+        let mut hidden = false; // 有格子合并在缓冲区
         if let Some(ref mut piece) = self.current_piece {
             let mut shift_y = 0;
             // 遍历当前块的y轴，并且当前位置的y不会超过地图的高度
@@ -315,25 +1116,69 @@ impl Tetris {
                         let x = piece.x + shift_x as isize;
                         self.game_map[piece.y + shift_y][x as usize] =
                             piece.states[piece.current_state as usize][shift_y][shift_x];
+                        if piece.y + shift_y < self.config.buffer_rows {
+                            hidden = true;
+                        } else {
+                            visible = true;
+                        }
                     }
                     shift_x += 1;
                 }
                 shift_y += 1;
             }
-            // 合并一个块后增加分数
-            to_add += self.current_level;
+            self.nb_pieces += 1;
+            self.events.push(GameEvent::PieceLocked(piece.kind));
+        }
+        // 方块完全在可见区域上方合并时游戏结束，开启部分合并规则时只要有一格在上方就结束
+        if hidden && !visible {
+            self.top_out(TopOut::Lock);
+        } else if hidden && self.config.partial_lock_out {
+            self.top_out(TopOut::PartialLock);
+        }
+        // 检查是否有可以删除的行，按消除前的级别计分
+        let level = self.current_level;
+        let lines = self.check_lines();
+        if tspin != TSpin::None {
+            self.events.push(GameEvent::TSpin(tspin, lines));
+        }
+        // 连续两次困难的消除(连消4行或者T旋消除)才有back-to-back奖励，普通消除会中断，不消除的合并不影响
+        let difficult = lines == 4 || (tspin != TSpin::None && lines > 0);
+        let back_to_back = difficult && self.back_to_back;
+        let perfect_clear = lines > 0 && self.game_map.iter().all(|line| line.iter().all(|case| *case == 0));
+        if perfect_clear {
+            self.events.push(GameEvent::PerfectClear(lines));
         }
-        self.update_score(to_add);
-        // 检查是否有可以删除的行
-        self.check_lines();
+        if lines > 0 {
+            self.combo = Some(self.combo.map_or(0, |combo| combo + 1));
+            self.back_to_back = difficult;
+        } else {
+            self.combo = None;
+        }
+        let points = self.scoring.lock(&LockResult {
+            lines,
+            level,
+            tspin,
+            combo: self.combo.unwrap_or(0),
+            back_to_back,
+            perfect_clear,
+        });
+        self.update_score(points);
         // 当前块已经被处理过了，所以设置为None
         self.current_piece = None;
+        self.hold_used = false;
+        self.reset_lock();
+        self.fall_progress = 0.0;
+        // 合并后立即取出下一个方块，新方块放不下时在同一个tick就结束游戏
+        // 这样游戏结束只由引擎决定，回放录像时也会在同样的tick结束
+        if !self.game_over {
+            self.spawn();
+        }
     }
 
-    fn check_lines(&mut self) {
-        let mut remove_num = 0;
+    // 删除所有填满的行，返回删除的行数
+    fn check_lines(&mut self) -> u32 {
+        let mut removed = Vec::new(); // 被消除的行在消除前的行号
         let mut y = 0;
-        let mut score_add = 0;
         // 遍历网格的每一行
         while y < self.game_map.len() {
             let mut complete = true;
@@ -344,125 +1189,387 @@ impl Tetris {
                     break
                 }
             }
-            // 如果这一行可以消除
-            if complete == true {
-                score_add += self.current_level;
+            // 如果这一行可以消除，下面的行移上来，y不变
+            if complete {
                 self.game_map.remove(y);
-                remove_num += 1;
-                y -= 1;
+                removed.push(y + removed.len());
+            } else {
+                y += 1;
             }
-            y += 1;
         }
-        // 连消4行
-        if remove_num == 4 {
-            // A "tetris"!
-            score_add += 1000;
+        let remove_num = removed.len();
+        if remove_num > 0 {
+            self.events.push(GameEvent::LinesCleared(removed));
         }
-        self.update_score(score_add);
-        while self.game_map.len() < 16 {
+        while self.game_map.len() < self.config.buffer_rows + self.config.height {
             self.increase_line();
-            // 补上消除的行，保证网格还是16*10
-            self.game_map.insert(0, vec![0, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
+            // 补上消除的行，保证网格的大小不变
+            self.game_map.insert(0, vec![0; self.config.width]);
         }
+        remove_num as u32
     }    
 }
 
-pub fn handle_events(tetris: &mut Tetris, quit: &mut bool, timer: &mut SystemTime,
-                event_pump: &mut sdl2::EventPump) -> bool {
-    // 一个块正在下落
-    let mut make_permanent = false;
-    if let Some(ref mut piece) = tetris.current_piece {
-        let mut tmp_x = piece.x;
-        let mut tmp_y = piece.y;
-
-        for event in event_pump.poll_iter() {
-            match event {
-                Event::Quit { .. } |
-                Event::KeyDown { keycode: Some(Keycode::Escape), .. } => {
-                    *quit = true;
-                    break
-                }
-                Event::KeyDown { keycode: Some(Keycode::Down), .. } => {
-                    *timer = SystemTime::now();// 更新下落的计时器
-                    tmp_y += 1;
-                }
-                Event::KeyDown { keycode: Some(Keycode::Right), .. } => {
-                    tmp_x += 1;
-                }
-                Event::KeyDown { keycode: Some(Keycode::Left), .. } => {
-                    tmp_x -= 1;
-                }
-                Event::KeyDown { keycode: Some(Keycode::Up), .. } => {
-                    piece.rotate(&tetris.game_map);
-                }
-                Event::KeyDown { keycode: Some(Keycode::Space), .. } => {
-                    let x = piece.x;
-                    let mut y = piece.y;
-                    // 手动快速下降到底部或有冲突不能移动
-                    while piece.change_position(&tetris.game_map, x, y + 1) == true {
-                        y += 1;
-                    }
-                    // 不能移动了，所以标记为需要合并到网格地图
-                    make_permanent = true;
-                }
-                _ => {}
+fn field<T: FromStr>(values: &HashMap<&str, &str>, key: &str) -> Option<T> {
+    values.get(key)?.parse::<T>().ok()
+}
+
+// 可以为空的值保存为none
+fn option_to_string<T: ToString>(value: Option<T>) -> String {
+    value.map_or("none".to_string(), |value| value.to_string())
+}
+
+fn option_field<T: FromStr>(values: &HashMap<&str, &str>, key: &str) -> Option<Option<T>> {
+    match *values.get(key)? {
+        "none" => Some(None),
+        value => value.parse::<T>().ok().map(Some),
+    }
+}
+
+// 操作保存为 tick数 操作名，例如 "1520 left"
+pub fn action_from_string(text: &str) -> Option<(u64, Action)> {
+    let (tick, name) = text.split_once(' ')?;
+    Some((tick.parse().ok()?, Action::from_name(name)?))
+}
+
+// 方块保存为 类型 x y 状态，最后一次操作是旋转时再加上旋转操作和踢墙偏移，例如 "T 3 20 1 cw 0"
+fn piece_to_string(piece: &Option<Tetrimino>) -> String {
+    match *piece {
+        Some(ref piece) => {
+            let mut text = format!("{} {} {} {}", piece.kind.name(), piece.x, piece.y, piece.current_state);
+            if let Some((action, kick)) = piece.last_rotation {
+                text += &format!(" {} {}", action.name(), kick);
             }
+            text
         }
-        // 根据按键后的坐标位置移动方块
-        if !make_permanent {
-            // 如果不能移动，且当前y的值也没有变化，说明已经移动到最下面了，需要合并方块到网格
-            if piece.change_position(&tetris.game_map, tmp_x, tmp_y) == false && tmp_y != piece.y {
-                make_permanent = true;
-            }
+        None => "none".to_string(),
+    }
+}
+
+fn piece_from_string(text: &str) -> Option<Option<Tetrimino>> {
+    if text == "none" {
+        return Some(None);
+    }
+    let words = text.split_whitespace().collect::<Vec<&str>>();
+    let (kind, x, y, state, rotation) = match words.as_slice() {
+        [kind, x, y, state, rotation @ ..] => (kind, x, y, state, rotation),
+        _ => return None,
+    };
+    let mut piece = match kinds_from_string(kind)?.as_slice() {
+        [kind] => kind.create(),
+        _ => return None,
+    };
+    piece.x = x.parse().ok()?;
+    piece.y = y.parse().ok()?;
+    piece.current_state = state.parse().ok().filter(|state| (*state as usize) < piece.states.len())?;
+    piece.last_rotation = match rotation {
+        [] => None,
+        [action, kick] => Some((Action::from_name(action)?, kick.parse().ok()?)),
+        _ => return None,
+    };
+    Some(Some(piece))
+}
+
+pub fn update_tetris(tetris: & mut Tetris) -> bool {
+    if !tetris.game_over {
+        tetris.spawn();
+    }
+    if tetris.game_over {
+        print_game_information(tetris);
+        return false;
+    }
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::randomizer::RandomizerKind;
+
+    fn empty_map() -> Vec<Vec<u8>> {
+        vec![vec![0; 10]; 20]
+    }
+
+    fn piece_at(kind: TetriminoKind, state: u8, x: isize, y: usize) -> Tetrimino {
+        let mut piece = kind.create();
+        piece.current_state = state;
+        piece.x = x;
+        piece.y = y;
+        piece
+    }
+
+    // 从底部开始按行填入网格，#为垃圾格子，.为空
+    fn fill_rows(tetris: &mut Tetris, rows: &[&str]) {
+        let height = tetris.game_map.len();
+        for (i, row) in rows.iter().rev().enumerate() {
+            tetris.game_map[height - 1 - i] = row.chars().map(|c| if c == '#' { GARBAGE } else { 0 }).collect();
         }
     }
-    if make_permanent {
-        // 合并方块后，更新计时器
+
+    // 把方块放在第x列直接落到底合并
+    fn drop_piece(tetris: &mut Tetris, kind: TetriminoKind, state: u8, x: isize) -> u32 {
+        let mut piece = piece_at(kind, state, x, 0);
+        piece.y = piece.landing_y(&tetris.game_map);
+        tetris.current_piece = Some(piece);
+        let score = tetris.score;
         tetris.make_permanent();
-        *timer = SystemTime::now();
+        tetris.score - score
     }
-    make_permanent
-}
 
-// 判断是否需要处理下落的时间到了
-fn is_time_over(tetris: &Tetris, timer: &SystemTime) -> bool {
-    match timer.elapsed() {
-        Ok(elapsed) => {
-            // 得到毫秒值
-            let millis = elapsed.as_secs() as u32 * 1000 + elapsed.subsec_nanos() / 1_000_000;
-            millis > LEVEL_TIMES[tetris.current_level as usize - 1]
+    #[test]
+    fn rotation_without_obstacles_uses_first_kick() {
+        let game_map = empty_map();
+        for kind in TETRIMINO_KINDS {
+            let mut piece = piece_at(kind, 0, 4, 5);
+            assert_eq!(piece.rotate(&game_map), Some(0));
+            assert_eq!((piece.current_state, piece.x, piece.y), (1, 4, 5));
         }
-        Err(_) => false,
     }
-}
 
-pub fn falling(tetris: & mut Tetris, timer: &mut SystemTime) {
-    if is_time_over(&tetris, &timer) {
-        let mut make_permanent = false;
-        if let Some(ref mut piece) = tetris.current_piece {
-          let x = piece.x;
-          let y = piece.y + 1;
-          make_permanent = !piece.change_position(&tetris.game_map,
-             x, y);
+    #[test]
+    fn i_kicks_off_right_wall() {
+        // 竖着的I贴着右墙，R->2 第一个位置出界，第二个偏移(-1, 0)
+        let game_map = empty_map();
+        let mut piece = piece_at(TetriminoKind::I, 1, 7, 5);
+        assert_eq!(piece.rotate(&game_map), Some(1));
+        assert_eq!((piece.current_state, piece.x, piece.y), (2, 6, 5));
+    }
+
+    #[test]
+    fn kick_moves_piece_up() {
+        // J的 0->R 前两个位置都被挡住，第三个偏移(-1, +1)向左上移动
+        let mut game_map = empty_map();
+        game_map[7][4] = GARBAGE;
+        game_map[7][5] = GARBAGE;
+        let mut piece = piece_at(TetriminoKind::J, 0, 4, 5);
+        assert_eq!(piece.rotate(&game_map), Some(2));
+        assert_eq!((piece.current_state, piece.x, piece.y), (1, 3, 4));
+    }
+
+    #[test]
+    fn ccw_kicks_are_reversed_cw_kicks() {
+        // T的 R->0 贴着左墙，用 0->R 第二个偏移取反得到的(+1, 0)
+        let game_map = empty_map();
+        let mut piece = piece_at(TetriminoKind::T, 1, -1, 5);
+        assert_eq!(piece.rotate_ccw(&game_map), Some(1));
+        assert_eq!((piece.current_state, piece.x, piece.y), (0, 0, 5));
+    }
+
+    #[test]
+    fn rotate_180_kicks_up_from_floor() {
+        // T的 0->2 下面被挡住，第二个偏移(0, +1)向上移动一格
+        let mut game_map = empty_map();
+        game_map[7][5] = GARBAGE;
+        let mut piece = piece_at(TetriminoKind::T, 0, 4, 5);
+        assert_eq!(piece.rotate_180(&game_map), Some(1));
+        assert_eq!((piece.current_state, piece.x, piece.y), (2, 4, 4));
+
+        let mut piece = piece_at(TetriminoKind::O, 0, 4, 5);
+        assert_eq!(piece.rotate_180(&game_map), Some(0));
+        assert_eq!((piece.current_state, piece.x, piece.y), (2, 4, 5));
+    }
+
+    #[test]
+    fn rotation_fails_when_every_kick_collides() {
+        // 竖着的I放在一条只有一格宽的井里，所有偏移都会冲突
+        let mut game_map = empty_map();
+        for row in game_map.iter_mut().skip(5) {
+            *row = vec![GARBAGE; 10];
+            row[6] = 0;
         }
-        if make_permanent {
-          tetris.make_permanent();
-        }        
-        *timer = SystemTime::now();
+        let mut piece = piece_at(TetriminoKind::I, 1, 4, 6);
+        assert_eq!(piece.rotate(&game_map), None);
+        assert_eq!(piece.rotate_ccw(&game_map), None);
+        assert_eq!((piece.current_state, piece.x, piece.y), (1, 4, 6));
     }
-}
 
-pub fn update_tetris(tetris: & mut Tetris) -> bool {
-    let mut ret = true;
-    if tetris.current_piece.is_none() {
-        let current_piece = tetris.create_new_tetrimino();
-        if !current_piece.test_current_position(&tetris.game_map) {     
-            print_game_information(tetris);       
-            ret = false; // 新创建的方块就已经冲突了，说明游戏结束了
-        } else {
-            tetris.current_piece = Some(current_piece);
-            ret = true;
-        }       
+    #[test]
+    fn back_to_back_tetrises_keep_the_combo() {
+        let mut tetris = Tetris::with_seed(&Config::default(), 1);
+        fill_rows(&mut tetris, &[
+            ".....#....",
+            ".#########", ".#########", ".#########", ".#########",
+            ".#########", ".#########", ".#########", ".#########",
+        ]);
+        // 竖着的I在状态R时占据方块中的第2列，放在x为-2时落到第0列
+        assert_eq!(drop_piece(&mut tetris, TetriminoKind::I, 1, -2), 800);
+        assert_eq!((tetris.combo, tetris.back_to_back), (Some(0), true));
+        assert_eq!(drop_piece(&mut tetris, TetriminoKind::I, 1, -2), 1200 + 50);
+        assert_eq!((tetris.combo, tetris.back_to_back), (Some(1), true));
+        // 没有消除时中断连续消除，但是保留back-to-back
+        assert_eq!(drop_piece(&mut tetris, TetriminoKind::O, 0, 3), 0);
+        assert_eq!((tetris.combo, tetris.back_to_back), (None, true));
+        assert_eq!(tetris.current_level, 1);
+    }
+
+    #[test]
+    fn clearing_every_cell_is_a_perfect_clear() {
+        let mut tetris = Tetris::with_seed(&Config::default(), 1);
+        fill_rows(&mut tetris, &["####....##"]);
+        assert_eq!(drop_piece(&mut tetris, TetriminoKind::I, 0, 4), 100 + 800);
+        assert!(tetris.drain_events().contains(&GameEvent::PerfectClear(1)));
+
+        // 网格中还留有格子时不算，只有消除一行和连续消除的分数
+        fill_rows(&mut tetris, &["#.........", "####....##"]);
+        assert_eq!(drop_piece(&mut tetris, TetriminoKind::I, 0, 4), 100 + 50);
+        assert!(!tetris.drain_events().contains(&GameEvent::PerfectClear(1)));
+    }
+
+    // 最后一次操作是使用了第kick个偏移的旋转的T
+    fn rotated_t(tetris: &Tetris, state: u8, x: isize, rows_above_floor: usize, rotation: Action, kick: usize) -> Tetrimino {
+        let mut piece = piece_at(TetriminoKind::T, state, x, tetris.game_map.len() - rows_above_floor);
+        piece.last_rotation = Some((rotation, kick));
+        piece
+    }
+
+    #[test]
+    fn tspin_double_with_three_corners() {
+        let mut tetris = Tetris::with_seed(&Config::default(), 1);
+        fill_rows(&mut tetris, &[".#........", "#...######", "##.#######"]);
+        let piece = rotated_t(&tetris, 2, 1, 3, Action::RotateCw, 0);
+        assert_eq!(tetris.detect_tspin(&piece), TSpin::Full);
+        tetris.current_piece = Some(piece);
+        tetris.make_permanent();
+        assert_eq!(tetris.score, 1200);
+        assert!(tetris.drain_events().contains(&GameEvent::TSpin(TSpin::Full, 2)));
     }
-    ret
-}
\ No newline at end of file
+
+    #[test]
+    fn tspin_mini_when_front_corner_is_open() {
+        // 朝上的T贴着地面，下面两个角是地面，上面只有一个角被占据
+        let mut tetris = Tetris::with_seed(&Config::default(), 1);
+        fill_rows(&mut tetris, &["#.........", "...#######"]);
+        let piece = rotated_t(&tetris, 0, 0, 2, Action::RotateCw, 0);
+        assert_eq!(tetris.detect_tspin(&piece), TSpin::Mini);
+        // 使用第5个踢墙偏移时算作T旋，180度旋转除外
+        let piece = rotated_t(&tetris, 0, 0, 2, Action::RotateCcw, 4);
+        assert_eq!(tetris.detect_tspin(&piece), TSpin::Full);
+        let piece = rotated_t(&tetris, 0, 0, 2, Action::Rotate180, 4);
+        assert_eq!(tetris.detect_tspin(&piece), TSpin::Mini);
+    }
+
+    #[test]
+    fn no_tspin_without_rotation_or_three_corners() {
+        let mut tetris = Tetris::with_seed(&Config::default(), 1);
+        fill_rows(&mut tetris, &["#.........", "...#######"]);
+        let mut piece = rotated_t(&tetris, 0, 0, 2, Action::RotateCw, 0);
+        piece.last_rotation = None;
+        assert_eq!(tetris.detect_tspin(&piece), TSpin::None);
+        // 只有地面的两个角被占据
+        fill_rows(&mut tetris, &["..........", ".........."]);
+        let piece = rotated_t(&tetris, 0, 0, 2, Action::RotateCw, 0);
+        assert_eq!(tetris.detect_tspin(&piece), TSpin::None);
+    }
+
+    // 把第一个方块直接放到底部，每过shift_every个tick左右来回平移一格，返回过了多少个tick才合并
+    fn ticks_to_lock(settings: &[(&str, &str)], shift_every: Option<u64>) -> u64 {
+        let mut config = Config::default();
+        for (key, value) in settings {
+            assert!(config.set(key, value));
+        }
+        let mut tetris = Tetris::with_seed(&config, 1);
+        tetris.tick();
+        let piece = tetris.current_piece.as_mut().unwrap();
+        piece.y = piece.landing_y(&tetris.game_map);
+        let start = tetris.ticks();
+        let mut shifts = [Action::MoveLeft, Action::MoveRight].iter().cycle();
+        while tetris.nb_pieces == 0 {
+            tetris.tick();
+            if shift_every.is_some_and(|every| (tetris.ticks() - start).is_multiple_of(every)) {
+                assert!(tetris.apply(*shifts.next().unwrap()));
+            }
+        }
+        tetris.ticks() - start
+    }
+
+    #[test]
+    fn grounded_piece_locks_after_lock_delay() {
+        assert_eq!(ticks_to_lock(&[], None), 500);
+        assert_eq!(ticks_to_lock(&[("lock_delay", "200")], None), 200);
+    }
+
+    #[test]
+    fn shifts_reset_lock_delay_only_in_move_mode() {
+        assert_eq!(ticks_to_lock(&[("lock_reset", "move")], Some(100)), 15 * 100 + 500);
+        assert_eq!(ticks_to_lock(&[("lock_reset", "step")], Some(100)), 500);
+        // 重新计时的次数用完后就不再重新计时
+        assert_eq!(ticks_to_lock(&[("lock_resets", "3")], Some(100)), 3 * 100 + 500);
+    }
+
+    // 当前方块加上预览队列中的方块
+    fn first_pieces(config: &Config, seed: u64) -> String {
+        let mut tetris = Tetris::with_seed(config, seed);
+        tetris.tick();
+        let mut pieces: Vec<_> = tetris.current_piece.iter().map(|piece| piece.kind).collect();
+        pieces.extend(tetris.next_pieces());
+        kinds_to_string(&pieces)
+    }
+
+    #[test]
+    fn same_seed_gives_same_pieces() {
+        let mut config = Config::default();
+        for name in ["bag7", "bag14", "tgm", "nes", "uniform"] {
+            config.randomizer = RandomizerKind::from_name(name).unwrap();
+            assert_eq!(first_pieces(&config, 3), first_pieces(&config, 3));
+            assert_ne!(first_pieces(&config, 3), first_pieces(&config, 4));
+        }
+    }
+
+    #[test]
+    fn hold_spawns_the_swapped_in_piece() {
+        let mut tetris = Tetris::with_seed(&Config::default(), 1);
+        tetris.tick();
+        let first = tetris.current_piece.as_ref().unwrap().kind;
+        let next = tetris.next_pieces()[0];
+        tetris.drain_events();
+        tetris.apply(Action::Hold);
+        assert_eq!(tetris.drain_events(), [GameEvent::Hold(first), GameEvent::PieceSpawned(next)]);
+
+        tetris.apply(Action::HardDrop);
+        let current = tetris.current_piece.as_ref().unwrap().kind;
+        tetris.drain_events();
+        tetris.apply(Action::Hold);
+        assert_eq!(tetris.drain_events(), [GameEvent::Hold(current), GameEvent::PieceSpawned(first)]);
+        assert_eq!(tetris.current_piece.as_ref().unwrap().kind, first);
+    }
+
+    #[test]
+    fn only_applied_actions_are_recorded() {
+        let mut tetris = Tetris::with_seed(&Config::default(), 1);
+        let mut moves = 0;
+        for _ in 0..20 {
+            if tetris.apply(Action::MoveLeft) {
+                moves += 1;
+            }
+        }
+        assert!(moves > 0 && moves < 20);
+        assert!(!tetris.can_move(-1));
+        assert_eq!(tetris.actions().len(), moves);
+
+        // 没有在软降时松开不算，暂停时的操作也不算，暂停和继续本身总是记下
+        tetris.apply(Action::SoftDropRelease);
+        tetris.apply(Action::Pause);
+        tetris.apply(Action::MoveRight);
+        tetris.apply(Action::HardDrop);
+        tetris.apply(Action::Pause);
+        let names: Vec<_> = tetris.actions()[moves..].iter().map(|&(_, action)| action.name()).collect();
+        assert_eq!(names, ["pause", "pause"]);
+    }
+
+    #[test]
+    fn soft_drop_follows_the_key_while_paused() {
+        let mut tetris = Tetris::with_seed(&Config::default(), 1);
+        tetris.apply(Action::SoftDrop);
+        tetris.apply(Action::Pause);
+        tetris.apply(Action::SoftDropRelease);
+        tetris.apply(Action::Pause);
+        assert!(!tetris.soft_drop);
+
+        tetris.apply(Action::Pause);
+        tetris.apply(Action::SoftDrop);
+        tetris.apply(Action::Pause);
+        assert!(tetris.soft_drop);
+        let names: Vec<_> = tetris.actions().iter().map(|&(_, action)| action.name()).collect();
+        assert_eq!(names, ["soft_drop", "pause", "soft_drop_release", "pause", "pause", "soft_drop", "pause"]);
+    }
+}